@@ -0,0 +1,15 @@
+#!/bin/sh
+# Rebuilds the wasm fixtures of the upgrade test: this crate as the deployed
+# contract and fixtures/upgraded as its next release. Run it after any change
+# to the contract interface or storage layout.
+set -e
+
+cd "$(dirname "$0")/.."
+
+export RUSTFLAGS="-C target-cpu=mvp"
+
+cargo build --target wasm32-unknown-unknown --release
+cp target/wasm32-unknown-unknown/release/collateralized_loans.wasm fixtures/
+
+cargo build --manifest-path fixtures/upgraded/Cargo.toml --target wasm32-unknown-unknown --release
+cp fixtures/upgraded/target/wasm32-unknown-unknown/release/upgraded.wasm fixtures/
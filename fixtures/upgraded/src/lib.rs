@@ -0,0 +1,33 @@
+//! Stand-in for a later release of the loan contract, used by the upgrade
+//! test. It reads a few keys the loan contract wrote to show that storage
+//! survives the code swap.
+#![no_std]
+use soroban_sdk::{contract, contractimpl, contracttype, Address, Env};
+
+#[derive(Clone)]
+#[contracttype]
+pub enum DataKey {
+    Admin,
+    TotalCash,
+}
+
+#[contract]
+pub struct UpgradedContract;
+
+#[contractimpl]
+impl UpgradedContract {
+    pub fn code_version() -> u32 {
+        2
+    }
+
+    pub fn read_administrator(env: Env) -> Address {
+        env.storage().instance().get(&DataKey::Admin).unwrap()
+    }
+
+    pub fn get_total_supply(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::TotalCash)
+            .unwrap_or(0)
+    }
+}
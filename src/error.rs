@@ -0,0 +1,42 @@
+use soroban_sdk::contracterror;
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    NotInitialized = 1,
+    AlreadyInitialized = 2,
+    InsufficientLiquidity = 3,
+    LoanNotFound = 4,
+    InvalidAmount = 5,
+    InsufficientShares = 6,
+    AmountExceedsDebt = 7,
+    LoanNotLiquidatable = 8,
+    OracleNotConfigured = 9,
+    StalePrice = 10,
+    InvalidPrice = 11,
+    InvalidParameter = 12,
+    InsufficientCollateral = 13,
+    LoanNotDefaulted = 14,
+    TreasuryNotSet = 15,
+    InsufficientReserves = 16,
+    MathOverflow = 17,
+    DivisionByZero = 18,
+    LoanTooSmall = 19,
+    LoanTooLarge = 20,
+    Paused = 21,
+    AlreadyMigrated = 22,
+    Unauthorized = 23,
+    NoPendingAdmin = 24,
+    DelayTooShort = 25,
+    ChangeNotFound = 26,
+    ChangeNotReady = 27,
+    TokenInUse = 28,
+    InvalidCollateralRate = 29,
+    InvalidInterestRate = 30,
+    InvalidInstallments = 31,
+    CashMismatch = 32,
+    RequiresTimelock = 33,
+    InvalidLiquidationBonus = 34,
+    LegacyPoolNotEmpty = 35,
+}
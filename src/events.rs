@@ -0,0 +1,129 @@
+use soroban_sdk::{contracttype, symbol_short, Address, Env, IntoVal, Symbol, Val};
+
+use crate::{PendingChange, Role};
+
+/// Data of every `loan` event. Amounts are what the action moved: the
+/// collateral locked, released or seized, and the principal, interest and
+/// late fees lent or paid back.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanEvent {
+    pub loan_id: u64,
+    pub collateral: i128,
+    pub principal: i128,
+    pub interest: i128,
+    pub penalty: i128,
+    pub total_cash: i128,
+}
+
+/// Data of every `pool` event.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolEvent {
+    pub amount: i128,
+    pub shares: i128,
+    pub total_cash: i128,
+}
+
+pub fn supplied(env: &Env, lender: Address, data: PoolEvent) {
+    let topics = (symbol_short!("pool"), symbol_short!("supply"), lender);
+    env.events().publish(topics, data);
+}
+
+pub fn withdrawn(env: &Env, lender: Address, data: PoolEvent) {
+    let topics = (symbol_short!("pool"), symbol_short!("withdraw"), lender);
+    env.events().publish(topics, data);
+}
+
+pub fn recovered(env: &Env, from: Address, data: PoolEvent) {
+    let topics = (symbol_short!("pool"), symbol_short!("recover"), from);
+    env.events().publish(topics, data);
+}
+
+pub fn reserves_accrued(env: &Env, amount: i128, total_reserves: i128) {
+    let topics = (symbol_short!("pool"), symbol_short!("reserves"));
+    env.events().publish(topics, (amount, total_reserves));
+}
+
+pub fn reserves_claimed(env: &Env, treasury: Address, amount: i128, total_reserves: i128) {
+    let topics = (symbol_short!("pool"), symbol_short!("claim"), treasury);
+    env.events().publish(topics, (amount, total_reserves));
+}
+
+pub fn loan_opened(env: &Env, borrower: Address, data: LoanEvent) {
+    let topics = (symbol_short!("loan"), symbol_short!("opened"), borrower);
+    env.events().publish(topics, data);
+}
+
+pub fn borrowed(env: &Env, borrower: Address, data: LoanEvent) {
+    let topics = (symbol_short!("loan"), symbol_short!("borrow"), borrower);
+    env.events().publish(topics, data);
+}
+
+pub fn loan_repaid(env: &Env, borrower: Address, data: LoanEvent) {
+    let topics = (symbol_short!("loan"), symbol_short!("repaid"), borrower);
+    env.events().publish(topics, data);
+}
+
+pub fn collateral_added(env: &Env, borrower: Address, data: LoanEvent) {
+    let topics = (symbol_short!("loan"), symbol_short!("coll_add"), borrower);
+    env.events().publish(topics, data);
+}
+
+pub fn collateral_removed(env: &Env, borrower: Address, data: LoanEvent) {
+    let topics = (symbol_short!("loan"), symbol_short!("coll_rem"), borrower);
+    env.events().publish(topics, data);
+}
+
+pub fn loan_defaulted(env: &Env, borrower: Address, data: LoanEvent) {
+    let topics = (symbol_short!("loan"), symbol_short!("default"), borrower);
+    env.events().publish(topics, data);
+}
+
+pub fn loan_liquidated(env: &Env, borrower: Address, liquidator: Address, data: LoanEvent) {
+    let topics = (
+        symbol_short!("loan"),
+        symbol_short!("liquidate"),
+        borrower,
+        liquidator,
+    );
+    env.events().publish(topics, data);
+}
+
+pub fn param_changed<V: IntoVal<Env, Val>>(env: &Env, name: Symbol, value: V) {
+    let topics = (symbol_short!("admin"), name);
+    env.events().publish(topics, value);
+}
+
+pub fn role_granted(env: &Env, role: Role, account: Address) {
+    let topics = (symbol_short!("role"), symbol_short!("grant"), account);
+    env.events().publish(topics, role);
+}
+
+pub fn role_revoked(env: &Env, role: Role, account: Address) {
+    let topics = (symbol_short!("role"), symbol_short!("revoke"), account);
+    env.events().publish(topics, role);
+}
+
+pub fn change_queued(env: &Env, change: PendingChange) {
+    let topics = (symbol_short!("timelock"), symbol_short!("queue"), change.id);
+    env.events().publish(topics, change);
+}
+
+pub fn change_executed(env: &Env, change: PendingChange) {
+    let topics = (
+        symbol_short!("timelock"),
+        symbol_short!("execute"),
+        change.id,
+    );
+    env.events().publish(topics, change);
+}
+
+pub fn change_cancelled(env: &Env, change: PendingChange) {
+    let topics = (
+        symbol_short!("timelock"),
+        symbol_short!("cancel"),
+        change.id,
+    );
+    env.events().publish(topics, change);
+}
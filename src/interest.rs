@@ -0,0 +1,89 @@
+use soroban_sdk::{contracttype, Env};
+
+use crate::{
+    math::{mul_div_ceil, mul_div_floor, RATE_SCALE},
+    DataKey, Error,
+};
+
+/// Kinked borrow rate curve. All values are annual rates or ratios in
+/// millionths.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InterestRateModel {
+    /// Borrow rate at zero utilization.
+    pub base_rate: i128,
+    /// Rate added between zero and optimal utilization.
+    pub slope_1: i128,
+    /// Rate added between optimal and full utilization.
+    pub slope_2: i128,
+    /// Utilization where the curve switches from `slope_1` to `slope_2`.
+    pub optimal_utilization: i128,
+}
+
+impl InterestRateModel {
+    /// A curve that charges `rate` whatever the utilization.
+    pub fn flat(rate: i128) -> Self {
+        InterestRateModel {
+            base_rate: rate,
+            slope_1: 0,
+            slope_2: 0,
+            optimal_utilization: RATE_SCALE,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.base_rate >= 0
+            && self.slope_1 >= 0
+            && self.slope_2 >= 0
+            && self.optimal_utilization > 0
+            && self.optimal_utilization <= RATE_SCALE
+    }
+
+    /// Rounds up, as it sets what borrowers owe.
+    pub fn borrow_rate(&self, utilization: i128) -> Result<i128, Error> {
+        // A curve with its kink at full utilization never reaches `slope_2`.
+        if utilization <= self.optimal_utilization || self.optimal_utilization >= RATE_SCALE {
+            return Ok(
+                self.base_rate + mul_div_ceil(self.slope_1, utilization, self.optimal_utilization)?
+            );
+        }
+
+        let excess = utilization - self.optimal_utilization;
+
+        Ok(self.base_rate
+            + self.slope_1
+            + mul_div_ceil(self.slope_2, excess, RATE_SCALE - self.optimal_utilization)?)
+    }
+
+    /// What lenders earn: the borrow rate spread over the whole pool, less
+    /// the share kept as reserves.
+    pub fn supply_rate(&self, utilization: i128, reserve_factor: i128) -> Result<i128, Error> {
+        let spread = mul_div_floor(self.borrow_rate(utilization)?, utilization, RATE_SCALE)?;
+
+        mul_div_floor(spread, RATE_SCALE - reserve_factor, RATE_SCALE)
+    }
+}
+
+/// Share of the pool that is lent out, in millionths. `cash` can be below
+/// zero once reserves outgrow the cash of a fully lent pool, which counts as
+/// full utilization.
+pub fn utilization(cash: i128, borrows: i128) -> Result<i128, Error> {
+    if borrows <= 0 {
+        return Ok(0);
+    }
+
+    let utilization = mul_div_floor(borrows, RATE_SCALE, cash.max(0) + borrows)?;
+
+    Ok(utilization.min(RATE_SCALE))
+}
+
+pub fn read_model(env: &Env) -> Result<InterestRateModel, Error> {
+    env.storage()
+        .instance()
+        .get(&DataKey::RateModel)
+        .ok_or(Error::NotInitialized)
+}
+
+pub fn write_model(env: &Env, model: &InterestRateModel) {
+    env.storage().instance().set(&DataKey::RateModel, model);
+}
@@ -1,9 +1,50 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, token, Address, Env, Vec};
+use soroban_sdk::{
+    contract, contractimpl, contracttype, symbol_short, token, Address, BytesN, Env, Symbol, Vec,
+};
+
+mod error;
+mod events;
+mod interest;
+mod math;
+mod migration;
+mod oracle;
+mod params;
+mod pause;
+mod roles;
+mod timelock;
+
+pub use error::Error;
+pub use events::{LoanEvent, PoolEvent};
+pub use interest::InterestRateModel;
+use math::{add, mul, mul_div_ceil, mul_div_floor, sub};
+pub use math::{INDEX_SCALE, RATE_SCALE};
+pub use migration::{LegacyBorrower, STORAGE_VERSION};
+pub use oracle::{OracleConfig, PriceData, PriceOracle, PriceOracleClient};
+pub use params::{MAX_GRACE_PERIOD, MAX_INSTALLMENTS, MAX_INTEREST_RATE, MAX_LIQUIDATION_BONUS};
+pub use pause::{Action, PauseState};
+pub use roles::Role;
+pub use timelock::{ParamChange, PendingChange, TIMELOCK_DELAY};
 
 #[contract]
 pub struct CollateralizedLoanContract;
 
+/// Time between two installments of a loan, in seconds.
+pub const INSTALLMENT_PERIOD: u64 = 30 * 24 * 60 * 60;
+
+/// Seconds per day, the period the penalty rate is charged over.
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Seconds per year used to turn annual rates into per-second rates.
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+pub(crate) const DAY_IN_LEDGERS: u32 = 17280;
+
+/// Loans live in persistent storage and are kept alive for a month past the
+/// last time they were touched.
+pub(crate) const LOAN_BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+pub(crate) const LOAN_LIFETIME_THRESHOLD: u32 = LOAN_BUMP_AMOUNT - DAY_IN_LEDGERS;
+
 #[derive(Clone)]
 #[contracttype]
 pub enum DataKey {
@@ -17,17 +58,77 @@ pub enum DataKey {
     Borrower,
     Debt,
     Installments,
+    TotalCash,
+    Loan(u64),
+    BorrowerLoans(Address),
+    NextLoanId,
+    TotalBorrows,
+    TotalShares,
+    Shares(Address),
+    BorrowIndex,
+    LastAccrual,
+    Oracle,
+    LiquidationThreshold,
+    LiquidationBonus,
+    RateModel,
+    GracePeriod,
+    PenaltyRate,
+    PenaltyLenderShare,
+    Treasury,
+    ReserveFactor,
+    TotalReserves,
+    Decimals(Address),
+    MinLoan,
+    MaxLoan,
+    PauseState,
+    Version,
+    // Layout of the first release, only read by `migrate`.
     TotalSupply,
     Borrowers,
     BorrowersAddresses,
+    Role(Role, Address),
+    PendingAdmin,
+    PendingChanges,
+    NextChangeId,
 }
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Borrower {
-    pub address: Address,
+pub struct Loan {
+    pub id: u64,
+    pub borrower: Address,
     pub amount_locked: i128,
+    pub principal: i128,
     pub debt: i128,
+    pub interest: i128,
+    pub borrow_index: i128,
+    pub installment_amount: i128,
+    /// Late fees owed on top of `debt` and `interest`.
+    pub penalty: i128,
+    pub penalty_accrued_at: u64,
+    pub due_dates: Vec<u64>,
+    pub opened_at: u64,
+    /// Last due date. Zero until something is borrowed.
+    pub due_at: u64,
+}
+
+/// Pool accounting as of the current ledger. Cash is what the contract holds,
+/// borrows what borrowers owe with interest, and reserves the part of both
+/// kept for the protocol.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolState {
+    pub total_cash: i128,
+    pub total_borrows: i128,
+    pub total_reserves: i128,
+    pub total_shares: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Installment {
+    pub due_date: u64,
+    pub amount: i128,
 }
 
 #[contractimpl]
@@ -37,22 +138,181 @@ impl CollateralizedLoanContract {
         e.storage().instance().has(&key)
     }
 
-    pub fn read_administrator(e: Env) -> Address {
+    pub fn read_administrator(e: Env) -> Result<Address, Error> {
         let key = DataKey::Admin;
-        e.storage().instance().get(&key).unwrap()
+        e.storage()
+            .instance()
+            .get(&key)
+            .ok_or(Error::NotInitialized)
     }
 
-    pub fn write_administrator(e: Env, id: Address) {
-        let key = DataKey::Admin;
-        e.storage().instance().set(&key, &id);
+    /// First step of an admin transfer. Nothing changes until `new_admin`
+    /// accepts.
+    pub fn propose_admin(e: Env, new_admin: Address) -> Result<(), Error> {
+        Self::read_administrator(e.clone())?.require_auth();
+
+        e.storage()
+            .instance()
+            .set(&DataKey::PendingAdmin, &new_admin);
+
+        events::param_changed(&e, symbol_short!("proposed"), new_admin);
+
+        Ok(())
+    }
+
+    pub fn accept_admin(e: Env) -> Result<(), Error> {
+        let new_admin: Address = e
+            .storage()
+            .instance()
+            .get(&DataKey::PendingAdmin)
+            .ok_or(Error::NoPendingAdmin)?;
+
+        new_admin.require_auth();
+
+        e.storage().instance().set(&DataKey::Admin, &new_admin);
+        e.storage().instance().remove(&DataKey::PendingAdmin);
+
+        events::param_changed(&e, symbol_short!("admin"), new_admin);
+
+        Ok(())
+    }
+
+    pub fn get_pending_admin(e: Env) -> Option<Address> {
+        e.storage().instance().get(&DataKey::PendingAdmin)
+    }
+
+    /// Gives `account` a role. The admin role only moves through
+    /// `propose_admin`.
+    pub fn grant_role(e: Env, role: Role, account: Address) -> Result<(), Error> {
+        Self::read_administrator(e.clone())?.require_auth();
+
+        if role == Role::Admin {
+            return Err(Error::InvalidParameter);
+        }
+
+        roles::grant(&e, role, &account);
+
+        events::role_granted(&e, role, account);
+
+        Ok(())
+    }
+
+    pub fn revoke_role(e: Env, role: Role, account: Address) -> Result<(), Error> {
+        Self::read_administrator(e.clone())?.require_auth();
+
+        if role == Role::Admin {
+            return Err(Error::InvalidParameter);
+        }
+
+        roles::revoke(&e, role, &account);
+
+        events::role_revoked(&e, role, account);
+
+        Ok(())
+    }
+
+    /// Whether `account` may act as `role`. The admin holds every role.
+    pub fn has_role(e: Env, role: Role, account: Address) -> bool {
+        roles::has_role(&e, role, &account)
+    }
+
+    /// Schedules `change` to take effect at `eta`, which has to be at least
+    /// `TIMELOCK_DELAY` away. Rate changes need the risk manager role, token
+    /// changes the admin. Returns the id of the change.
+    pub fn queue_change(
+        env: Env,
+        caller: Address,
+        change: ParamChange,
+        eta: u64,
+    ) -> Result<u64, Error> {
+        roles::require_role(&env, change.role(), &caller)?;
+
+        let pending = timelock::queue(&env, change, eta)?;
+        let id = pending.id;
+
+        events::change_queued(&env, pending);
+
+        Ok(id)
+    }
+
+    /// Applies a queued change once its `eta` has passed. Anyone can call it.
+    pub fn execute_change(env: Env, id: u64) -> Result<(), Error> {
+        let pending = Self::get_pending_change(env.clone(), id)?;
+
+        if env.ledger().timestamp() < pending.eta {
+            return Err(Error::ChangeNotReady);
+        }
+
+        timelock::take(&env, id)?;
+        timelock::apply(&env, &pending.change)?;
+
+        env.storage().instance().bump(100, 100);
+
+        events::change_executed(&env, pending);
+
+        Ok(())
+    }
+
+    /// Drops a queued change. Needs the same role as queuing it.
+    pub fn cancel_change(env: Env, caller: Address, id: u64) -> Result<(), Error> {
+        let pending = Self::get_pending_change(env.clone(), id)?;
+
+        roles::require_role(&env, pending.change.role(), &caller)?;
+
+        timelock::take(&env, id)?;
+
+        events::change_cancelled(&env, pending);
+
+        Ok(())
+    }
+
+    pub fn get_pending_change(env: Env, id: u64) -> Result<PendingChange, Error> {
+        timelock::read_pending(&env)
+            .iter()
+            .find(|pending| pending.id == id)
+            .ok_or(Error::ChangeNotFound)
     }
 
+    /// Queued changes in the order they were queued.
+    pub fn get_pending_changes(env: Env) -> Vec<PendingChange> {
+        timelock::read_pending(&env)
+    }
+
+    /// Sets up the pool. `collateral_rate` is the share of the collateral
+    /// value that can be borrowed and `interest_rate` the annual borrow
+    /// rate, both in millionths. The rate stays flat until a curve is set
+    /// with `set_rate_model`. The decimals of both tokens are read here to
+    /// value collateral across them. The same bounds as the setters apply.
+    /// Can only run once.
     pub fn initialize(
         env: Env,
+        admin: Address,
         loan_token: Address,
         collateral_token: Address,
         collateral_rate: i128,
-    ) {
+        interest_rate: i128,
+        installments: u32,
+    ) -> Result<(), Error> {
+        if env.storage().instance().has(&DataKey::Admin) {
+            return Err(Error::AlreadyInitialized);
+        }
+
+        admin.require_auth();
+
+        params::check_collateral_rate(&env, collateral_rate)?;
+        params::check_interest_rate(&env, interest_rate)?;
+        params::check_installments(installments)?;
+
+        env.storage().instance().set(&DataKey::Admin, &admin);
+
+        for token in [&loan_token, &collateral_token] {
+            let decimals = token::Client::new(&env, token).decimals();
+
+            env.storage()
+                .instance()
+                .set(&DataKey::Decimals(token.clone()), &decimals);
+        }
+
         env.storage()
             .instance()
             .set(&DataKey::LoanToken, &loan_token);
@@ -61,369 +321,1983 @@ impl CollateralizedLoanContract {
             .instance()
             .set(&DataKey::CollateralToken, &collateral_token);
 
-        let interest_rate: i128 = 15000; // TODO: Should be configurated through params
-
-        env.storage()
-            .instance()
-            .set(&DataKey::InterestRate, &interest_rate);
+        interest::write_model(&env, &InterestRateModel::flat(interest_rate));
 
         env.storage()
             .instance()
             .set(&DataKey::CollateralRate, &collateral_rate);
 
-        let installments = 3;
-
         env.storage()
             .instance()
             .set(&DataKey::Installments, &installments);
-    }
-
-    pub fn request_loan(env: Env, from: Address, amount: i128) -> i128 {
-        // Perhaps this check should be enabled...
-        if amount == 0 {
-            panic!("deposit amount must not be zero");
-        }
-        // Make sure `from` address authorized the deposit call with all the
-        // arguments.
-        from.require_auth();
 
-        let collateral_token = env
-            .storage()
-            .instance()
-            .get(&DataKey::CollateralToken)
-            .unwrap();
-
-        let collateral_rate = env
-            .storage()
+        env.storage()
             .instance()
-            .get(&DataKey::CollateralRate)
-            .unwrap_or(0);
+            .set(&DataKey::BorrowIndex, &INDEX_SCALE);
 
-        let mut total_supply: i128 = env
-            .storage()
+        env.storage()
             .instance()
-            .get(&DataKey::TotalSupply)
-            .unwrap_or(0);
+            .set(&DataKey::LastAccrual, &env.ledger().timestamp());
 
-        let debt = amount * collateral_rate;
+        migration::write_version(&env, STORAGE_VERSION);
 
-        if total_supply < debt {
-            panic!("total supply is not enough");
-        }
+        Ok(())
+    }
 
-        // Transfer token from `from` to this contract address.
-        token::Client::new(&env, &collateral_token).transfer(
-            &from,
-            &env.current_contract_address(),
-            &amount,
-        );
+    /// Replaces the code of this contract. Storage is kept as is, so call
+    /// `migrate` afterwards if the new code changed its layout.
+    pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), Error> {
+        Self::read_administrator(env.clone())?.require_auth();
 
-        let mut borrowers: Vec<Borrower> = env
-            .storage()
-            .instance()
-            .get(&DataKey::Borrowers)
-            .unwrap_or(Vec::new(&env));
+        env.deployer()
+            .update_current_contract_wasm(new_wasm_hash.clone());
 
-        let mut borrower_addresses: Vec<Address> = env
-            .storage()
-            .instance()
-            .get(&DataKey::BorrowersAddresses)
-            .unwrap_or(Vec::new(&env));
+        events::param_changed(&env, symbol_short!("upgrade"), new_wasm_hash);
 
-        borrower_addresses.push_back(from.clone());
+        Ok(())
+    }
 
-        env.storage()
-            .instance()
-            .set(&DataKey::BorrowersAddresses, &borrower_addresses);
+    /// Brings storage up to `STORAGE_VERSION`, one version at a time.
+    /// `collateral_rate`, in millionths, replaces the stored rate, which the
+    /// first release kept in other units. Returns the version reached.
+    pub fn migrate(env: Env, collateral_rate: i128) -> Result<u32, Error> {
+        Self::read_administrator(env.clone())?.require_auth();
 
-        let loan_token = env.storage().instance().get(&DataKey::LoanToken).unwrap();
+        let version = migration::read_version(&env);
 
-        total_supply -= debt;
+        if version >= STORAGE_VERSION {
+            return Err(Error::AlreadyMigrated);
+        }
 
-        env.storage()
-            .instance()
-            .set(&DataKey::TotalSupply, &total_supply);
+        if version < 1 {
+            migration::migrate_v1(&env, collateral_rate)?;
+        }
 
-        token::Client::new(&env, &loan_token).transfer(
-            &env.current_contract_address(),
-            &from,
-            &debt,
-        );
+        migration::write_version(&env, STORAGE_VERSION);
 
-        let borrower = Borrower {
-            address: from,
-            amount_locked: amount,
-            debt: debt, // paytime: paytime,
-        };
+        check_cash(&env)?;
 
-        borrowers.push_back(borrower);
+        env.storage().instance().bump(100, 100);
 
-        env.storage()
-            .instance()
-            .set(&DataKey::Borrowers, &borrowers);
+        events::param_changed(&env, symbol_short!("migrate"), STORAGE_VERSION);
 
-        env.storage().instance().bump(100, 100);
+        Ok(STORAGE_VERSION)
+    }
 
-        debt
+    /// Storage layout version, 0 for contracts deployed before versioning.
+    pub fn get_version(env: Env) -> u32 {
+        migration::read_version(&env)
     }
 
-    pub fn supply_loan_tokens(env: Env, from: Address, amount_to_lend: i128) {
-        if amount_to_lend == 0 {
-            panic!("loan amount must not be zero");
+    /// Locks `amount` of collateral and borrows as much as it allows in one
+    /// go, like `deposit_collateral` followed by `borrow` of
+    /// `max_borrowable`.
+    pub fn request_loan(env: Env, from: Address, amount: i128) -> Result<u64, Error> {
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
         }
+        pause::require_not_paused(&env, Action::Borrow)?;
 
+        // Make sure `from` address authorized the deposit call with all the
+        // arguments.
         from.require_auth();
 
-        let loan_token = env.storage().instance().get(&DataKey::LoanToken).unwrap();
+        let debt = Self::get_loan_amount(env.clone(), amount)?;
 
-        token::Client::new(&env, &loan_token).transfer(
-            &from,
-            &env.current_contract_address(),
-            &amount_to_lend,
-        );
+        let mut loan = open_loan(&env, &from, amount)?;
 
-        // env.storage().instance().set(&DataKey::Lender, &from);
-        let mut total_supply: i128 = env
-            .storage()
-            .instance()
-            .get(&DataKey::TotalSupply)
-            .unwrap_or(0);
+        let total_cash = draw(&env, &mut loan, debt)?;
 
-        total_supply += amount_to_lend;
+        write_loan(&env, &loan);
 
-        env.storage()
-            .instance()
-            .set(&DataKey::TotalSupply, &total_supply);
+        events::loan_opened(
+            &env,
+            from,
+            LoanEvent {
+                loan_id: loan.id,
+                collateral: amount,
+                principal: debt,
+                interest: 0,
+                penalty: 0,
+                total_cash,
+            },
+        );
+
+        check_cash(&env)?;
 
         env.storage().instance().bump(100, 100);
+
+        Ok(loan.id)
     }
 
-    pub fn get_loan_amount(env: Env, collateral_amount: i128) -> i128 {
-        let collateral_rate = env
-            .storage()
-            .instance()
-            .get(&DataKey::CollateralRate)
-            .unwrap_or(0);
+    /// Opens a loan holding `amount` of collateral and no debt yet. Borrow
+    /// against it with `borrow`.
+    pub fn deposit_collateral(env: Env, from: Address, amount: i128) -> Result<u64, Error> {
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
 
-        collateral_amount * collateral_rate
-    }
+        from.require_auth();
 
-    // pub fn make_monthly_payment(env: Env, amount: i128) {
-    //     let collateral_amount: i128 = env
-    //         .storage()
-    //         .instance()
-    //         .get(&DataKey::CollateralAmount)
-    //         .unwrap_or(0);
+        let loan = open_loan(&env, &from, amount)?;
 
-    //     let collateral_rate = env
-    //         .storage()
-    //         .instance()
-    //         .get(&DataKey::CollateralRate)
-    //         .unwrap_or(0);
+        write_loan(&env, &loan);
 
-    //     let installments = env
-    //         .storage()
-    //         .instance()
-    //         .get(&DataKey::Installments)
-    //         .unwrap_or(1);
+        events::collateral_added(&env, from, collateral_event(&env, loan.id, amount));
 
-    //     let loan_amount = collateral_amount * collateral_rate;
+        env.storage().instance().bump(100, 100);
 
-    //     let monthly_amount = loan_amount / installments;
+        Ok(loan.id)
+    }
 
-    //     let interest_rate: i128 = env
-    //         .storage()
-    //         .instance()
-    //         .get(&DataKey::InterestRate)
-    //         .unwrap();
+    /// Lends `amount` more against the collateral of `loan_id`, up to
+    /// `max_borrowable`. Every draw restarts the installment schedule over
+    /// the whole principal owed. Returns the outstanding debt.
+    pub fn borrow(env: Env, loan_id: u64, amount: i128) -> Result<i128, Error> {
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
 
-    //     let monthly_amount_to_pay = monthly_amount + (loan_amount * interest_rate / 1000000);
+        pause::require_not_paused(&env, Action::Borrow)?;
 
-    //     if amount < monthly_amount_to_pay {
-    //         panic!("monehtly payment amount is less than allowed")
-    //     }
+        let mut loan = read_loan(&env, loan_id)?;
 
-    //     let mut debt: i128 = env.storage().instance().get(&DataKey::Debt).unwrap();
+        loan.borrower.require_auth();
 
-    //     debt = debt - amount;
+        if amount > Self::max_borrowable(env.clone(), loan_id)? {
+            return Err(Error::InsufficientCollateral);
+        }
 
-    //     if debt < 0 {
-    //         debt = 0;
-    //     }
+        let total_cash = draw(&env, &mut loan, amount)?;
+
+        write_loan(&env, &loan);
+
+        events::borrowed(
+            &env,
+            loan.borrower.clone(),
+            LoanEvent {
+                loan_id,
+                collateral: 0,
+                principal: amount,
+                interest: 0,
+                penalty: 0,
+                total_cash,
+            },
+        );
 
-    //     let loan_token = env.storage().instance().get(&DataKey::LoanToken).unwrap();
+        check_cash(&env)?;
 
-    //     let borrower: Address = env.storage().instance().get(&DataKey::Borrower).unwrap();
+        env.storage().instance().bump(100, 100);
 
-    //     let lender: Address = env.storage().instance().get(&DataKey::Lender).unwrap();
+        outstanding(&loan)
+    }
 
-    //     token::Client::new(&env, &loan_token).transfer(&borrower, &lender, &amount);
+    /// How much more `loan_id` can borrow before its debt, interest
+    /// included, reaches the collateral rate.
+    pub fn max_borrowable(env: Env, loan_id: u64) -> Result<i128, Error> {
+        let loan = Self::get_loan(env.clone(), loan_id)?;
 
-    //     env.storage().instance().set(&DataKey::Debt, &debt);
+        let max_loan = Self::get_loan_amount(env, loan.amount_locked)?;
 
-    //     if debt <= 0 {
-    //         let collateral_token = env
-    //             .storage()
-    //             .instance()
-    //             .get(&DataKey::CollateralToken)
-    //             .unwrap();
+        Ok(sub(max_loan, outstanding(&loan)?)?.max(0))
+    }
 
-    //         token::Client::new(&env, &collateral_token).transfer(
-    //             &env.current_contract_address(),
-    //             &borrower,
-    //             &collateral_amount,
-    //         );
-    //     }
-    // }
+    pub fn supply_loan_tokens(
+        env: Env,
+        from: Address,
+        amount_to_lend: i128,
+    ) -> Result<i128, Error> {
+        if amount_to_lend <= 0 {
+            return Err(Error::InvalidAmount);
+        }
 
-    pub fn repay_loan(env: Env, from: Address, amount: i128) -> i128 {
-        let mut borrowers: Vec<Borrower> = env
-            .storage()
-            .instance()
-            .get(&DataKey::Borrowers)
-            .unwrap_or(Vec::new(&env));
+        pause::require_not_paused(&env, Action::Supply)?;
+
+        from.require_auth();
 
-        let mut borrowers_addresses: Vec<Address> = env
+        let loan_token = env
             .storage()
             .instance()
-            .get(&DataKey::BorrowersAddresses)
-            .unwrap_or(Vec::new(&env));
-
-        let index = borrowers_addresses.first_index_of(from).unwrap();
+            .get(&DataKey::LoanToken)
+            .ok_or(Error::NotInitialized)?;
 
-        let borrower = borrowers.get(index).unwrap();
+        accrue_interest(&env)?;
 
-        let loan_amount = borrower.debt;
+        // Shares are priced against the pool value before this deposit lands,
+        // so interest already earned stays with the existing lenders.
+        let pool_value = pool_value(&env)?;
 
-        let interest_rate: i128 = env
+        let total_shares: i128 = env
             .storage()
             .instance()
-            .get(&DataKey::InterestRate)
-            .unwrap();
+            .get(&DataKey::TotalShares)
+            .unwrap_or(0);
 
-        let total_amount_to_pay = loan_amount + (loan_amount * interest_rate / 1000000);
+        let shares = if total_shares == 0 || pool_value == 0 {
+            amount_to_lend
+        } else {
+            mul_div_floor(amount_to_lend, total_shares, pool_value)?
+        };
 
-        if amount != total_amount_to_pay {
-            panic!("payment amount is not correct");
+        if shares == 0 {
+            return Err(Error::InvalidAmount);
         }
 
-        // let lender: Address = env.storage().instance().get(&DataKey::Lender).unwrap();
-
-        borrower.address.require_auth();
-
-        let loan_token = env.storage().instance().get(&DataKey::LoanToken).unwrap();
-
         token::Client::new(&env, &loan_token).transfer(
-            &borrower.address,
+            &from,
             &env.current_contract_address(),
-            &total_amount_to_pay,
+            &amount_to_lend,
         );
 
-        let collateral_token = env
+        let mut total_cash: i128 = env
             .storage()
             .instance()
-            .get(&DataKey::CollateralToken)
-            .unwrap();
-
-        token::Client::new(&env, &collateral_token).transfer(
-            &env.current_contract_address(),
-            &borrower.address,
-            &borrower.amount_locked,
-        );
+            .get(&DataKey::TotalCash)
+            .unwrap_or(0);
 
-        borrowers_addresses.remove(index);
-        borrowers.remove(index);
+        total_cash = add(total_cash, amount_to_lend)?;
 
         env.storage()
             .instance()
-            .set(&DataKey::Borrowers, &borrowers);
+            .set(&DataKey::TotalCash, &total_cash);
+
+        let lender_shares = read_shares(&env, &from);
+
+        write_shares(&env, &from, add(lender_shares, shares)?);
 
         env.storage()
             .instance()
-            .set(&DataKey::BorrowersAddresses, &borrowers_addresses);
+            .set(&DataKey::TotalShares, &add(total_shares, shares)?);
+
+        events::supplied(
+            &env,
+            from,
+            PoolEvent {
+                amount: amount_to_lend,
+                shares,
+                total_cash,
+            },
+        );
 
-        let mut total_supply: i128 = env
-            .storage()
-            .instance()
-            .get(&DataKey::TotalSupply)
-            .unwrap_or(0);
+        check_cash(&env)?;
+
+        env.storage().instance().bump(100, 100);
+
+        Ok(shares)
+    }
+
+    /// Pays `amount` of loan tokens into the pool without taking shares, so
+    /// it raises the value of every lender's shares. Meant for proceeds of
+    /// collateral seized by `mark_default`. Returns the cash of the pool.
+    pub fn recover(env: Env, from: Address, amount: i128) -> Result<i128, Error> {
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        from.require_auth();
+
+        let loan_token = Self::get_loan_token(env.clone())?;
 
-        total_supply += total_amount_to_pay;
+        accrue_interest(&env)?;
+
+        token::Client::new(&env, &loan_token).transfer(
+            &from,
+            &env.current_contract_address(),
+            &amount,
+        );
+
+        let total_cash = add(Self::get_total_supply(env.clone()), amount)?;
 
         env.storage()
             .instance()
-            .set(&DataKey::TotalSupply, &total_supply);
+            .set(&DataKey::TotalCash, &total_cash);
+
+        events::recovered(
+            &env,
+            from,
+            PoolEvent {
+                amount,
+                shares: 0,
+                total_cash,
+            },
+        );
+
+        check_cash(&env)?;
 
         env.storage().instance().bump(100, 100);
 
-        0
+        Ok(total_cash)
     }
 
-    pub fn get_debt(env: Env, from: Address) -> i128 {
-        let borrowers: Vec<Borrower> = env
+    pub fn withdraw_loan_tokens(env: Env, from: Address, shares: i128) -> Result<i128, Error> {
+        if shares <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        pause::require_not_paused(&env, Action::Withdraw)?;
+
+        from.require_auth();
+
+        let lender_shares = read_shares(&env, &from);
+
+        if lender_shares < shares {
+            return Err(Error::InsufficientShares);
+        }
+
+        accrue_interest(&env)?;
+
+        let total_shares: i128 = env
             .storage()
             .instance()
-            .get(&DataKey::Borrowers)
-            .unwrap_or(Vec::new(&env));
+            .get(&DataKey::TotalShares)
+            .unwrap_or(0);
+
+        let amount = mul_div_floor(shares, pool_value(&env)?, total_shares)?;
 
-        let borrowers_addresses: Vec<Address> = env
+        let mut total_cash: i128 = env
             .storage()
             .instance()
-            .get(&DataKey::BorrowersAddresses)
-            .unwrap_or(Vec::new(&env));
+            .get(&DataKey::TotalCash)
+            .unwrap_or(0);
+
+        // Only cash on hand can be paid out; the part of the pool that is
+        // lent out comes back as borrowers repay.
+        if total_cash < amount {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        total_cash -= amount;
 
-        let index = borrowers_addresses.first_index_of(from).unwrap();
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalCash, &total_cash);
 
-        let borrower = borrowers.get(index).unwrap();
+        write_shares(&env, &from, lender_shares - shares);
 
-        let loan_amount = borrower.debt;
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalShares, &(total_shares - shares));
 
-        let interest_rate: i128 = env
+        let loan_token = env
             .storage()
             .instance()
-            .get(&DataKey::InterestRate)
-            .unwrap();
+            .get(&DataKey::LoanToken)
+            .ok_or(Error::NotInitialized)?;
+
+        token::Client::new(&env, &loan_token).transfer(
+            &env.current_contract_address(),
+            &from,
+            &amount,
+        );
 
-        let total_amount_to_pay = loan_amount + (loan_amount * interest_rate / 1000000);
+        events::withdrawn(
+            &env,
+            from,
+            PoolEvent {
+                amount,
+                shares,
+                total_cash,
+            },
+        );
 
-        return total_amount_to_pay;
-    }
+        check_cash(&env)?;
 
-    pub fn get_loan_token(env: Env) -> Address {
-        let loan_token = env.storage().instance().get(&DataKey::LoanToken).unwrap();
+        env.storage().instance().bump(100, 100);
 
-        loan_token
+        Ok(amount)
     }
 
-    pub fn get_collateral_token(env: Env) -> Address {
-        let collateral_token = env
+    /// What `collateral_amount` can borrow: its oracle value in loan tokens
+    /// times the collateral rate, in millionths.
+    pub fn get_loan_amount(env: Env, collateral_amount: i128) -> Result<i128, Error> {
+        if collateral_amount < 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        let collateral_token: Address = env
             .storage()
             .instance()
             .get(&DataKey::CollateralToken)
-            .unwrap();
-
-        collateral_token
-    }
+            .ok_or(Error::NotInitialized)?;
 
-    pub fn get_total_supply(env: Env) -> i128 {
-        let total_supply: i128 = env
+        let collateral_rate: i128 = env
             .storage()
             .instance()
-            .get(&DataKey::TotalSupply)
+            .get(&DataKey::CollateralRate)
             .unwrap_or(0);
 
-        total_supply
+        mul_div_floor(
+            oracle::value_of(&env, &collateral_token, collateral_amount)?,
+            collateral_rate,
+            RATE_SCALE,
+        )
     }
 
-    pub fn get_borrowers(env: Env) -> Vec<Borrower> {
-        let borrowers: Vec<Borrower> = env
+    pub fn repay_loan(env: Env, loan_id: u64, amount: i128) -> Result<i128, Error> {
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        pause::require_not_paused(&env, Action::Repay)?;
+
+        let mut loan = read_loan(&env, loan_id)?;
+
+        accrue_loan(&env, &mut loan, accrue_interest(&env)?)?;
+
+        let outstanding = outstanding(&loan)?;
+
+        if amount > outstanding {
+            return Err(Error::AmountExceedsDebt);
+        }
+
+        loan.borrower.require_auth();
+
+        let loan_token = env
             .storage()
             .instance()
-            .get(&DataKey::Borrowers)
-            .unwrap_or(Vec::new(&env));
+            .get(&DataKey::LoanToken)
+            .ok_or(Error::NotInitialized)?;
 
-        borrowers
-    }
+        token::Client::new(&env, &loan_token).transfer(
+            &loan.borrower,
+            &env.current_contract_address(),
+            &amount,
+        );
+
+        let penalty_paid = pay_penalty(&mut loan, amount);
+        let interest_paid = apply_payment(&mut loan, amount - penalty_paid);
+
+        let mut collateral_released = 0;
+
+        if loan.debt == 0 && loan.interest == 0 && loan.penalty == 0 {
+            collateral_released = loan.amount_locked;
+
+            let collateral_token = env
+                .storage()
+                .instance()
+                .get(&DataKey::CollateralToken)
+                .ok_or(Error::NotInitialized)?;
+
+            token::Client::new(&env, &collateral_token).transfer(
+                &env.current_contract_address(),
+                &loan.borrower,
+                &loan.amount_locked,
+            );
+
+            remove_loan(&env, &loan);
+        } else {
+            write_loan(&env, &loan);
+        }
+
+        let total_cash = return_to_pool(&env, amount - penalty_paid, penalty_paid)?;
+
+        events::loan_repaid(
+            &env,
+            loan.borrower,
+            LoanEvent {
+                loan_id,
+                collateral: collateral_released,
+                principal: amount - penalty_paid - interest_paid,
+                interest: interest_paid,
+                penalty: penalty_paid,
+                total_cash,
+            },
+        );
+
+        check_cash(&env)?;
+
+        env.storage().instance().bump(100, 100);
+
+        Ok(outstanding - amount)
+    }
+
+    /// Locks `amount` more collateral in an open loan. Returns the collateral
+    /// now locked.
+    pub fn add_collateral(env: Env, loan_id: u64, amount: i128) -> Result<i128, Error> {
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        let mut loan = read_loan(&env, loan_id)?;
+
+        loan.borrower.require_auth();
+
+        let collateral_token: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::CollateralToken)
+            .ok_or(Error::NotInitialized)?;
+
+        token::Client::new(&env, &collateral_token).transfer(
+            &loan.borrower,
+            &env.current_contract_address(),
+            &amount,
+        );
+
+        loan.amount_locked = add(loan.amount_locked, amount)?;
+
+        write_loan(&env, &loan);
+
+        events::collateral_added(&env, loan.borrower, collateral_event(&env, loan_id, amount));
+
+        env.storage().instance().bump(100, 100);
+
+        Ok(loan.amount_locked)
+    }
+
+    /// Releases `amount` of collateral as long as what stays locked can
+    /// still borrow the outstanding debt at the collateral rate. Returns the
+    /// collateral now locked.
+    pub fn remove_collateral(env: Env, loan_id: u64, amount: i128) -> Result<i128, Error> {
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        pause::require_not_paused(&env, Action::Borrow)?;
+
+        let mut loan = read_loan(&env, loan_id)?;
+
+        loan.borrower.require_auth();
+
+        if amount > loan.amount_locked {
+            return Err(Error::InsufficientCollateral);
+        }
+
+        accrue_loan(&env, &mut loan, accrue_interest(&env)?)?;
+
+        loan.amount_locked -= amount;
+
+        let outstanding = outstanding(&loan)?;
+
+        if Self::get_loan_amount(env.clone(), loan.amount_locked)? < outstanding {
+            return Err(Error::InsufficientCollateral);
+        }
+
+        let collateral_token: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::CollateralToken)
+            .ok_or(Error::NotInitialized)?;
+
+        token::Client::new(&env, &collateral_token).transfer(
+            &env.current_contract_address(),
+            &loan.borrower,
+            &amount,
+        );
+
+        // A loan with nothing borrowed and nothing locked is gone.
+        if loan.amount_locked == 0 && outstanding == 0 {
+            remove_loan(&env, &loan);
+        } else {
+            write_loan(&env, &loan);
+        }
+
+        events::collateral_removed(&env, loan.borrower, collateral_event(&env, loan_id, amount));
+
+        env.storage().instance().bump(100, 100);
+
+        Ok(loan.amount_locked)
+    }
+
+    /// Anyone can default a loan still owing `grace_period` seconds after its
+    /// last due date. Whatever is owed is written off, so lenders take the
+    /// loss through the pool value. The collateral goes to the treasury, which
+    /// sells it and pays the proceeds back to lenders with `recover`.
+    /// Returns the debt written off.
+    pub fn mark_default(env: Env, loan_id: u64) -> Result<i128, Error> {
+        pause::require_not_paused(&env, Action::Liquidate)?;
+
+        let mut loan = read_loan(&env, loan_id)?;
+
+        accrue_loan(&env, &mut loan, accrue_interest(&env)?)?;
+
+        let outstanding = outstanding(&loan)?;
+
+        let grace_period: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::GracePeriod)
+            .unwrap_or(0);
+
+        if outstanding == 0 || env.ledger().timestamp() <= loan.due_at.saturating_add(grace_period)
+        {
+            return Err(Error::LoanNotDefaulted);
+        }
+
+        let treasury = Self::get_treasury(env.clone()).ok_or(Error::TreasuryNotSet)?;
+
+        let collateral_token: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::CollateralToken)
+            .ok_or(Error::NotInitialized)?;
+
+        token::Client::new(&env, &collateral_token).transfer(
+            &env.current_contract_address(),
+            &treasury,
+            &loan.amount_locked,
+        );
+
+        let total_borrows: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalBorrows)
+            .unwrap_or(0);
+
+        env.storage().instance().set(
+            &DataKey::TotalBorrows,
+            &(total_borrows - loan.debt - loan.interest).max(0),
+        );
+
+        // Reserves were booked on the interest as it accrued. It will never
+        // be paid, so their share of it goes too, at the current factor.
+        let reserves_lost = mul_div_ceil(
+            loan.interest,
+            Self::get_reserve_factor(env.clone()),
+            RATE_SCALE,
+        )?;
+
+        env.storage().instance().set(
+            &DataKey::TotalReserves,
+            &(Self::get_reserves(env.clone()) - reserves_lost).max(0),
+        );
+
+        remove_loan(&env, &loan);
+
+        let total_cash: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalCash)
+            .unwrap_or(0);
+
+        events::loan_defaulted(
+            &env,
+            loan.borrower,
+            LoanEvent {
+                loan_id,
+                collateral: loan.amount_locked,
+                principal: loan.debt,
+                interest: loan.interest,
+                penalty: loan.penalty,
+                total_cash,
+            },
+        );
+
+        env.storage().instance().bump(100, 100);
+
+        Ok(outstanding)
+    }
+
+    /// Stops or resumes a single action.
+    pub fn set_paused(
+        env: Env,
+        caller: Address,
+        action: Action,
+        paused: bool,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::PauseGuardian, &caller)?;
+
+        let mut state = pause::read_state(&env);
+        state.set(action, paused);
+
+        write_pause_state(&env, &state);
+
+        Ok(())
+    }
+
+    /// Emergency stop of everything that adds risk. Repaying and adding
+    /// collateral stay open so borrowers can still get safer.
+    pub fn pause(env: Env, caller: Address) -> Result<(), Error> {
+        roles::require_role(&env, Role::PauseGuardian, &caller)?;
+
+        let state = PauseState {
+            supply: true,
+            borrow: true,
+            repay: false,
+            withdraw: true,
+            liquidate: true,
+        };
+
+        write_pause_state(&env, &state);
+
+        Ok(())
+    }
+
+    pub fn unpause(env: Env, caller: Address) -> Result<(), Error> {
+        roles::require_role(&env, Role::PauseGuardian, &caller)?;
+
+        write_pause_state(&env, &PauseState::default());
+
+        Ok(())
+    }
+
+    pub fn get_pause_state(env: Env) -> PauseState {
+        pause::read_state(&env)
+    }
+
+    /// Bounds on the principal a loan can owe after a draw.
+    pub fn set_loan_limits(
+        env: Env,
+        caller: Address,
+        min_loan: i128,
+        max_loan: i128,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        if min_loan < 0 || max_loan < min_loan {
+            return Err(Error::InvalidParameter);
+        }
+
+        env.storage().instance().set(&DataKey::MinLoan, &min_loan);
+        env.storage().instance().set(&DataKey::MaxLoan, &max_loan);
+
+        events::param_changed(&env, symbol_short!("loan_lim"), (min_loan, max_loan));
+
+        Ok(())
+    }
+
+    /// Minimum and maximum loan size. Unbounded until `set_loan_limits`.
+    pub fn get_loan_limits(env: Env) -> (i128, i128) {
+        let min_loan = env.storage().instance().get(&DataKey::MinLoan).unwrap_or(0);
+
+        let max_loan = env
+            .storage()
+            .instance()
+            .get(&DataKey::MaxLoan)
+            .unwrap_or(i128::MAX);
+
+        (min_loan, max_loan)
+    }
+
+    /// Seconds after its last due date before a loan can be defaulted, at
+    /// most `MAX_GRACE_PERIOD`. Lengthening it applies at once, shortening it
+    /// has to go through `queue_change`.
+    pub fn set_grace_period(env: Env, caller: Address, grace_period: u64) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        params::check_grace_period(grace_period)?;
+
+        if grace_period < Self::get_grace_period(env.clone()) {
+            return Err(Error::RequiresTimelock);
+        }
+
+        params::write_grace_period(&env, grace_period);
+
+        events::param_changed(&env, symbol_short!("grace"), grace_period);
+
+        Ok(())
+    }
+
+    pub fn get_grace_period(env: Env) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::GracePeriod)
+            .unwrap_or(0)
+    }
+
+    /// Late fees: `penalty_rate` is charged per day, in millionths, on
+    /// principal left unpaid past its due date. `lender_share`, in
+    /// millionths, of what is collected goes to the pool and the rest to the
+    /// treasury. Once set, raising the rate has to go through `queue_change`.
+    pub fn set_penalty_params(
+        env: Env,
+        caller: Address,
+        penalty_rate: i128,
+        lender_share: i128,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        params::check_penalty_params(penalty_rate, lender_share)?;
+
+        let current: Option<i128> = env.storage().instance().get(&DataKey::PenaltyRate);
+
+        if current.is_some_and(|current| penalty_rate > current) {
+            return Err(Error::RequiresTimelock);
+        }
+
+        params::write_penalty_params(&env, penalty_rate, lender_share);
+
+        events::param_changed(&env, symbol_short!("penalty"), (penalty_rate, lender_share));
+
+        Ok(())
+    }
+
+    /// Penalty rate and lender share set with `set_penalty_params`.
+    pub fn get_penalty_params(env: Env) -> (i128, i128) {
+        let penalty_rate = env
+            .storage()
+            .instance()
+            .get(&DataKey::PenaltyRate)
+            .unwrap_or(0);
+
+        let lender_share = env
+            .storage()
+            .instance()
+            .get(&DataKey::PenaltyLenderShare)
+            .unwrap_or(RATE_SCALE);
+
+        (penalty_rate, lender_share)
+    }
+
+    pub fn set_treasury(env: Env, treasury: Address) -> Result<(), Error> {
+        Self::read_administrator(env.clone())?.require_auth();
+
+        env.storage().instance().set(&DataKey::Treasury, &treasury);
+
+        events::param_changed(&env, symbol_short!("treasury"), treasury);
+
+        Ok(())
+    }
+
+    pub fn get_treasury(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Treasury)
+    }
+
+    /// Share of the interest, in millionths, set aside as protocol reserves
+    /// instead of going to lenders.
+    pub fn set_reserve_factor(
+        env: Env,
+        caller: Address,
+        reserve_factor: i128,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        if !(0..=RATE_SCALE).contains(&reserve_factor) {
+            return Err(Error::InvalidParameter);
+        }
+
+        // Interest accrued so far is split at the old factor.
+        accrue_interest(&env)?;
+
+        env.storage()
+            .instance()
+            .set(&DataKey::ReserveFactor, &reserve_factor);
+
+        events::param_changed(&env, symbol_short!("reserve"), reserve_factor);
+
+        Ok(())
+    }
+
+    pub fn get_reserve_factor(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::ReserveFactor)
+            .unwrap_or(0)
+    }
+
+    /// Reserves accrued up to the last action that touched the pool.
+    pub fn get_reserves(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::TotalReserves)
+            .unwrap_or(0)
+    }
+
+    /// Sends `amount` of the reserves to the treasury. Returns the reserves
+    /// left.
+    pub fn claim_reserves(env: Env, caller: Address, amount: i128) -> Result<i128, Error> {
+        roles::require_role(&env, Role::Treasurer, &caller)?;
+
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        let treasury = Self::get_treasury(env.clone()).ok_or(Error::TreasuryNotSet)?;
+
+        accrue_interest(&env)?;
+
+        let mut total_reserves = Self::get_reserves(env.clone());
+
+        if amount > total_reserves {
+            return Err(Error::InsufficientReserves);
+        }
+
+        let mut total_cash: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalCash)
+            .unwrap_or(0);
+
+        if amount > total_cash {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        let loan_token: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::LoanToken)
+            .ok_or(Error::NotInitialized)?;
+
+        token::Client::new(&env, &loan_token).transfer(
+            &env.current_contract_address(),
+            &treasury,
+            &amount,
+        );
+
+        total_cash -= amount;
+        total_reserves -= amount;
+
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalCash, &total_cash);
+
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalReserves, &total_reserves);
+
+        events::reserves_claimed(&env, treasury, amount, total_reserves);
+
+        check_cash(&env)?;
+
+        env.storage().instance().bump(100, 100);
+
+        Ok(total_reserves)
+    }
+
+    /// Sets the price feed. Once one is set, only `max_age` can change at
+    /// once; moving to another feed or scale has to go through
+    /// `queue_change`.
+    pub fn set_oracle(
+        env: Env,
+        caller: Address,
+        oracle: Address,
+        max_age: u64,
+        decimals: u32,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        let config = OracleConfig {
+            address: oracle,
+            max_age,
+            decimals,
+        };
+
+        oracle::check_config(&config)?;
+
+        if let Ok(current) = oracle::read_config(&env) {
+            if config.address != current.address || config.decimals != current.decimals {
+                return Err(Error::RequiresTimelock);
+            }
+        }
+
+        oracle::write_config(&env, &config);
+
+        events::param_changed(&env, symbol_short!("oracle"), config);
+
+        Ok(())
+    }
+
+    pub fn get_oracle(env: Env) -> Result<OracleConfig, Error> {
+        oracle::read_config(&env)
+    }
+
+    /// Share of the collateral value a loan can owe before it can be
+    /// liquidated, and the bonus liquidators get on top of what they repay,
+    /// both in millionths. Once set, lowering the threshold or raising the
+    /// bonus has to go through `queue_change`.
+    pub fn set_liquidation_params(
+        env: Env,
+        caller: Address,
+        liquidation_threshold: i128,
+        liquidation_bonus: i128,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        params::check_liquidation_params(&env, liquidation_threshold, liquidation_bonus)?;
+
+        let current: Option<i128> = env.storage().instance().get(&DataKey::LiquidationThreshold);
+
+        if let Some(current) = current {
+            let current_bonus: i128 = env
+                .storage()
+                .instance()
+                .get(&DataKey::LiquidationBonus)
+                .unwrap_or(0);
+
+            if liquidation_threshold < current || liquidation_bonus > current_bonus {
+                return Err(Error::RequiresTimelock);
+            }
+        }
+
+        params::write_liquidation_params(&env, liquidation_threshold, liquidation_bonus);
+
+        events::param_changed(
+            &env,
+            symbol_short!("liq_param"),
+            (liquidation_threshold, liquidation_bonus),
+        );
+
+        Ok(())
+    }
+
+    /// Lowers the share of the collateral value that can be borrowed, in
+    /// millionths, at once. Raising it has to go through `queue_change`.
+    pub fn set_collateral_rate(
+        env: Env,
+        caller: Address,
+        collateral_rate: i128,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        params::check_collateral_rate(&env, collateral_rate)?;
+
+        if collateral_rate > Self::get_collateral_rate(env.clone()) {
+            return Err(Error::RequiresTimelock);
+        }
+
+        params::write_collateral_rate(&env, collateral_rate);
+
+        events::param_changed(&env, symbol_short!("coll_rate"), collateral_rate);
+
+        Ok(())
+    }
+
+    pub fn get_collateral_rate(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::CollateralRate)
+            .unwrap_or(0)
+    }
+
+    /// Lowers the borrow rate at zero utilization at once. Raising it has to
+    /// go through `queue_change`. Interest accrued so far is settled at the
+    /// old rate first.
+    pub fn set_interest_rate(env: Env, caller: Address, interest_rate: i128) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        params::check_interest_rate(&env, interest_rate)?;
+
+        if interest_rate > interest::read_model(&env)?.base_rate {
+            return Err(Error::RequiresTimelock);
+        }
+
+        params::write_interest_rate(&env, interest_rate)?;
+
+        events::param_changed(&env, symbol_short!("int_rate"), interest_rate);
+
+        Ok(())
+    }
+
+    /// Number of installments of loans drawn from now on, at most
+    /// `MAX_INSTALLMENTS`.
+    pub fn set_installments(env: Env, caller: Address, installments: u32) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        params::check_installments(installments)?;
+
+        env.storage()
+            .instance()
+            .set(&DataKey::Installments, &installments);
+
+        events::param_changed(&env, symbol_short!("installs"), installments);
+
+        Ok(())
+    }
+
+    pub fn get_installments(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::Installments)
+            .unwrap_or(1)
+    }
+
+    /// Replaces the borrow rate curve at once, as long as it charges no more
+    /// at any utilization. Anything else has to go through `queue_change`.
+    /// Interest accrued so far is settled at the old rates first.
+    pub fn set_rate_model(
+        env: Env,
+        caller: Address,
+        base_rate: i128,
+        slope_1: i128,
+        slope_2: i128,
+        optimal_utilization: i128,
+    ) -> Result<(), Error> {
+        roles::require_role(&env, Role::RiskManager, &caller)?;
+
+        let model = InterestRateModel {
+            base_rate,
+            slope_1,
+            slope_2,
+            optimal_utilization,
+        };
+
+        params::check_rate_model(&model)?;
+
+        if !params::is_rate_cut(&interest::read_model(&env)?, &model) {
+            return Err(Error::RequiresTimelock);
+        }
+
+        params::write_rate_model(&env, &model)?;
+
+        events::param_changed(&env, Symbol::new(&env, "rate_model"), model);
+
+        Ok(())
+    }
+
+    pub fn get_rate_model(env: Env) -> Result<InterestRateModel, Error> {
+        interest::read_model(&env)
+    }
+
+    /// Annual borrow and supply rates at the current utilization, in
+    /// millionths.
+    pub fn get_current_rates(env: Env) -> Result<(i128, i128), Error> {
+        let model = interest::read_model(&env)?;
+
+        let utilization = current_utilization(&env)?;
+
+        Ok((
+            model.borrow_rate(utilization)?,
+            model.supply_rate(utilization, Self::get_reserve_factor(env))?,
+        ))
+    }
+
+    /// Collateral value times the liquidation threshold over the debt, in
+    /// millionths. A loan below 1000000 can be liquidated.
+    pub fn get_health_factor(env: Env, loan_id: u64) -> Result<i128, Error> {
+        let loan = Self::get_loan(env.clone(), loan_id)?;
+
+        health_factor(&env, &loan)
+    }
+
+    pub fn liquidate(
+        env: Env,
+        liquidator: Address,
+        loan_id: u64,
+        repay_amount: i128,
+    ) -> Result<i128, Error> {
+        if repay_amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        pause::require_not_paused(&env, Action::Liquidate)?;
+
+        liquidator.require_auth();
+
+        let mut loan = read_loan(&env, loan_id)?;
+
+        accrue_loan(&env, &mut loan, accrue_interest(&env)?)?;
+
+        if health_factor(&env, &loan)? >= RATE_SCALE {
+            return Err(Error::LoanNotLiquidatable);
+        }
+
+        let outstanding = outstanding(&loan)?;
+
+        if repay_amount > outstanding {
+            return Err(Error::AmountExceedsDebt);
+        }
+
+        let liquidation_bonus: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::LiquidationBonus)
+            .unwrap_or(0);
+
+        let collateral_token: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::CollateralToken)
+            .ok_or(Error::NotInitialized)?;
+
+        // The liquidator gets the repaid value in collateral plus the bonus,
+        // capped by what the loan actually holds.
+        let seized = oracle::amount_for(
+            &env,
+            &collateral_token,
+            mul_div_floor(
+                repay_amount,
+                add(RATE_SCALE, liquidation_bonus)?,
+                RATE_SCALE,
+            )?,
+        )?
+        .min(loan.amount_locked);
+
+        let loan_token = env
+            .storage()
+            .instance()
+            .get(&DataKey::LoanToken)
+            .ok_or(Error::NotInitialized)?;
+
+        token::Client::new(&env, &loan_token).transfer(
+            &liquidator,
+            &env.current_contract_address(),
+            &repay_amount,
+        );
+
+        token::Client::new(&env, &collateral_token).transfer(
+            &env.current_contract_address(),
+            &liquidator,
+            &seized,
+        );
+
+        let penalty_paid = pay_penalty(&mut loan, repay_amount);
+        let interest_paid = apply_payment(&mut loan, repay_amount - penalty_paid);
+
+        loan.amount_locked -= seized;
+
+        if loan.debt == 0 && loan.interest == 0 && loan.penalty == 0 {
+            if loan.amount_locked > 0 {
+                token::Client::new(&env, &collateral_token).transfer(
+                    &env.current_contract_address(),
+                    &loan.borrower,
+                    &loan.amount_locked,
+                );
+            }
+
+            remove_loan(&env, &loan);
+        } else {
+            write_loan(&env, &loan);
+        }
+
+        let total_cash = return_to_pool(&env, repay_amount - penalty_paid, penalty_paid)?;
+
+        events::loan_liquidated(
+            &env,
+            loan.borrower,
+            liquidator,
+            LoanEvent {
+                loan_id,
+                collateral: seized,
+                principal: repay_amount - penalty_paid - interest_paid,
+                interest: interest_paid,
+                penalty: penalty_paid,
+                total_cash,
+            },
+        );
+
+        check_cash(&env)?;
+
+        env.storage().instance().bump(100, 100);
+
+        Ok(seized)
+    }
+
+    pub fn get_next_installment(env: Env, loan_id: u64) -> Result<Installment, Error> {
+        let loan = Self::get_loan(env.clone(), loan_id)?;
+
+        let installments = loan.due_dates.len();
+
+        // Collateral deposited but nothing borrowed yet.
+        if installments == 0 {
+            return Ok(Installment {
+                due_date: 0,
+                amount: 0,
+            });
+        }
+
+        // Principal is repaid in equal parts, in order, so the principal paid
+        // so far tells which installment is next. Interest accrued up to now
+        // is always due with it, and the last one also picks up any rounding
+        // remainder.
+        let principal_paid = loan.principal - loan.debt;
+
+        let paid_installments = if loan.installment_amount == 0 {
+            0
+        } else {
+            (principal_paid / loan.installment_amount) as u32
+        };
+
+        let index = paid_installments.min(installments - 1);
+
+        let principal_due = if index == installments - 1 {
+            loan.debt
+        } else {
+            (index as i128 + 1) * loan.installment_amount - principal_paid
+        };
+
+        Ok(Installment {
+            due_date: loan.due_dates.get(index).unwrap(),
+            amount: add(add(loan.penalty, loan.interest)?, principal_due)?,
+        })
+    }
+
+    pub fn get_debt(env: Env, loan_id: u64) -> Result<i128, Error> {
+        let loan = Self::get_loan(env.clone(), loan_id)?;
+
+        outstanding(&loan)
+    }
+
+    pub fn get_loan_token(env: Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::LoanToken)
+            .ok_or(Error::NotInitialized)
+    }
+
+    pub fn get_collateral_token(env: Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::CollateralToken)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Loan tokens held by the pool, not what lenders deposited. See
+    /// `get_pool_state`.
+    pub fn get_total_supply(env: Env) -> i128 {
+        let total_cash: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalCash)
+            .unwrap_or(0);
+
+        total_cash
+    }
+
+    pub fn get_pool_state(env: Env) -> Result<PoolState, Error> {
+        let (_, total_borrows, total_reserves) = project_interest(&env)?;
+
+        Ok(PoolState {
+            total_cash: Self::get_total_supply(env.clone()),
+            total_borrows,
+            total_reserves,
+            total_shares: Self::get_total_shares(env),
+        })
+    }
+
+    pub fn get_shares(env: Env, lender: Address) -> i128 {
+        read_shares(&env, &lender)
+    }
+
+    pub fn get_total_shares(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::TotalShares)
+            .unwrap_or(0)
+    }
+
+    pub fn get_shares_value(env: Env, shares: i128) -> Result<i128, Error> {
+        if shares < 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        let total_shares: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalShares)
+            .unwrap_or(0);
+
+        if total_shares == 0 {
+            return Ok(0);
+        }
+
+        mul_div_floor(shares, pool_value(&env)?, total_shares)
+    }
+
+    /// The loan with its interest and penalty brought up to now, without
+    /// writing them back.
+    pub fn get_loan(env: Env, loan_id: u64) -> Result<Loan, Error> {
+        let mut loan = read_loan(&env, loan_id)?;
+
+        accrue_loan(&env, &mut loan, borrow_index(&env)?)?;
+
+        Ok(loan)
+    }
+
+    pub fn get_loans_of(env: Env, borrower: Address) -> Vec<Loan> {
+        let mut loans = Vec::new(&env);
+
+        for loan_id in borrower_loans(&env, &borrower).iter() {
+            if let Ok(loan) = read_loan(&env, loan_id) {
+                loans.push_back(loan);
+            }
+        }
+
+        loans
+    }
+
+    /// Open loans among the ids `start..start + limit`, in id order, and the
+    /// `start` of the next page, if there is one. Closed loans leave gaps, so
+    /// a page can hold fewer than `limit` loans, or none.
+    pub fn list_loans(env: Env, start: u64, limit: u32) -> (Vec<Loan>, Option<u64>) {
+        let next_loan_id: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::NextLoanId)
+            .unwrap_or(0);
+
+        let end = start.saturating_add(limit as u64).min(next_loan_id);
+
+        let mut loans = Vec::new(&env);
+
+        for loan_id in start..end {
+            if let Ok(loan) = read_loan(&env, loan_id) {
+                loans.push_back(loan);
+            }
+        }
+
+        let next_page = if end < next_loan_id { Some(end) } else { None };
+
+        (loans, next_page)
+    }
+}
+
+fn write_pause_state(env: &Env, state: &PauseState) {
+    pause::write_state(env, state);
+
+    events::param_changed(env, symbol_short!("pause"), state.clone());
+}
+
+/// Takes `collateral` from `borrower` and returns a new loan holding it, not
+/// stored yet.
+fn open_loan(env: &Env, borrower: &Address, collateral: i128) -> Result<Loan, Error> {
+    let collateral_token: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::CollateralToken)
+        .ok_or(Error::NotInitialized)?;
+
+    token::Client::new(env, &collateral_token).transfer(
+        borrower,
+        &env.current_contract_address(),
+        &collateral,
+    );
+
+    let loan_id: u64 = env
+        .storage()
+        .instance()
+        .get(&DataKey::NextLoanId)
+        .unwrap_or(0);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::NextLoanId, &(loan_id + 1));
+
+    Ok(Loan {
+        id: loan_id,
+        borrower: borrower.clone(),
+        amount_locked: collateral,
+        principal: 0,
+        debt: 0,
+        interest: 0,
+        borrow_index: accrue_interest(env)?,
+        installment_amount: 0,
+        penalty: 0,
+        penalty_accrued_at: env.ledger().timestamp(),
+        due_dates: Vec::new(env),
+        opened_at: env.ledger().timestamp(),
+        due_at: 0,
+    })
+}
+
+/// Lends `amount` out of the pool to the borrower of `loan` and schedules
+/// the installments of everything it now owes. Returns the cash left.
+fn draw(env: &Env, loan: &mut Loan, amount: i128) -> Result<i128, Error> {
+    accrue_loan(env, loan, accrue_interest(env)?)?;
+
+    let mut total_cash: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalCash)
+        .unwrap_or(0);
+
+    if total_cash < amount {
+        return Err(Error::InsufficientLiquidity);
+    }
+
+    let (min_loan, max_loan) = CollateralizedLoanContract::get_loan_limits(env.clone());
+
+    let debt = add(loan.debt, amount)?;
+
+    if debt < min_loan {
+        return Err(Error::LoanTooSmall);
+    }
+
+    if debt > max_loan {
+        return Err(Error::LoanTooLarge);
+    }
+
+    total_cash -= amount;
+
+    env.storage()
+        .instance()
+        .set(&DataKey::TotalCash, &total_cash);
+
+    let total_borrows: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalBorrows)
+        .unwrap_or(0);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::TotalBorrows, &add(total_borrows, amount)?);
+
+    let loan_token: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::LoanToken)
+        .ok_or(Error::NotInitialized)?;
+
+    token::Client::new(env, &loan_token).transfer(
+        &env.current_contract_address(),
+        &loan.borrower,
+        &amount,
+    );
+
+    loan.debt = debt;
+
+    schedule(env, loan);
+
+    Ok(total_cash)
+}
+
+/// Spreads what `loan` owes over fresh installments starting now.
+fn schedule(env: &Env, loan: &mut Loan) {
+    let installments: u32 = env
+        .storage()
+        .instance()
+        .get(&DataKey::Installments)
+        .unwrap_or(1);
+
+    let now = env.ledger().timestamp();
+
+    let mut due_dates = Vec::new(env);
+
+    for installment in 1..=installments {
+        due_dates.push_back(now + installment as u64 * INSTALLMENT_PERIOD);
+    }
+
+    loan.principal = loan.debt;
+    loan.installment_amount = loan.debt / installments as i128;
+    loan.due_at = now + installments as u64 * INSTALLMENT_PERIOD;
+    loan.due_dates = due_dates;
+}
+
+/// Everything the lenders own: cash held by the contract plus what borrowers
+/// owe, interest included, as of the current ledger.
+fn pool_value(env: &Env) -> Result<i128, Error> {
+    let total_cash: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalCash)
+        .unwrap_or(0);
+
+    let (_, total_borrows, total_reserves) = project_interest(env)?;
+
+    sub(add(total_cash, total_borrows)?, total_reserves)
+}
+
+/// Borrow index, total borrows and reserves grown by the interest accrued
+/// since the last accrual, without writing them back.
+fn project_interest(env: &Env) -> Result<(i128, i128, i128), Error> {
+    let borrow_index: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::BorrowIndex)
+        .unwrap_or(INDEX_SCALE);
+
+    let total_borrows: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalBorrows)
+        .unwrap_or(0);
+
+    let total_reserves: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalReserves)
+        .unwrap_or(0);
+
+    let now = env.ledger().timestamp();
+
+    let last_accrual: u64 = env
+        .storage()
+        .instance()
+        .get(&DataKey::LastAccrual)
+        .unwrap_or(now);
+
+    if now <= last_accrual {
+        return Ok((borrow_index, total_borrows, total_reserves));
+    }
+
+    let total_cash: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalCash)
+        .unwrap_or(0);
+
+    // The rate is set by the utilization left behind by the last action.
+    let utilization = interest::utilization(total_cash - total_reserves, total_borrows)?;
+
+    let interest_rate = match interest::read_model(env) {
+        Ok(model) => model.borrow_rate(utilization)?,
+        Err(_) => 0,
+    };
+
+    let elapsed = (now - last_accrual) as i128;
+
+    // The index sets what every loan owes, so it rounds up.
+    let new_index = add(
+        borrow_index,
+        mul_div_ceil(
+            borrow_index,
+            mul(interest_rate, elapsed)?,
+            RATE_SCALE * SECONDS_PER_YEAR as i128,
+        )?,
+    )?;
+
+    let new_total_borrows = mul_div_floor(total_borrows, new_index, borrow_index)?;
+
+    let reserve_factor = CollateralizedLoanContract::get_reserve_factor(env.clone());
+
+    let new_reserves = mul_div_floor(
+        new_total_borrows - total_borrows,
+        reserve_factor,
+        RATE_SCALE,
+    )?;
+
+    Ok((
+        new_index,
+        new_total_borrows,
+        add(total_reserves, new_reserves)?,
+    ))
+}
+
+fn current_utilization(env: &Env) -> Result<i128, Error> {
+    let total_cash: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalCash)
+        .unwrap_or(0);
+
+    let (_, total_borrows, total_reserves) = project_interest(env)?;
+
+    interest::utilization(total_cash - total_reserves, total_borrows)
+}
+
+fn borrow_index(env: &Env) -> Result<i128, Error> {
+    Ok(project_interest(env)?.0)
+}
+
+/// Brings the borrow index, total borrows and reserves up to the current
+/// ledger timestamp and returns the new index.
+fn accrue_interest(env: &Env) -> Result<i128, Error> {
+    let (borrow_index, total_borrows, total_reserves) = project_interest(env)?;
+
+    let reserves_added = total_reserves - CollateralizedLoanContract::get_reserves(env.clone());
+
+    env.storage()
+        .instance()
+        .set(&DataKey::BorrowIndex, &borrow_index);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::TotalBorrows, &total_borrows);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::TotalReserves, &total_reserves);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::LastAccrual, &env.ledger().timestamp());
+
+    if reserves_added > 0 {
+        events::reserves_accrued(env, reserves_added, total_reserves);
+    }
+
+    Ok(borrow_index)
+}
+
+fn collateral_event(env: &Env, loan_id: u64, collateral: i128) -> LoanEvent {
+    let total_cash: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalCash)
+        .unwrap_or(0);
+
+    LoanEvent {
+        loan_id,
+        collateral,
+        principal: 0,
+        interest: 0,
+        penalty: 0,
+        total_cash,
+    }
+}
+
+/// `TotalCash` must be backed by the loan tokens the contract holds. Checked
+/// after every call that moves them. The balance can be higher, as anyone can
+/// send tokens to the contract, and the surplus is simply not lent out.
+fn check_cash(env: &Env) -> Result<(), Error> {
+    let total_cash = CollateralizedLoanContract::get_total_supply(env.clone());
+
+    let loan_token = CollateralizedLoanContract::get_loan_token(env.clone())?;
+
+    let balance = token::Client::new(env, &loan_token).balance(&env.current_contract_address());
+
+    if balance < total_cash {
+        return Err(Error::CashMismatch);
+    }
+
+    Ok(())
+}
+
+/// Settles as much of the penalty as `amount` covers and returns that part.
+fn pay_penalty(loan: &mut Loan, amount: i128) -> i128 {
+    let penalty_paid = amount.min(loan.penalty);
+
+    loan.penalty -= penalty_paid;
+
+    penalty_paid
+}
+
+/// Settles interest first and only then reduces the principal. Returns the
+/// part of `amount` that went to interest.
+fn apply_payment(loan: &mut Loan, amount: i128) -> i128 {
+    let interest_paid = amount.min(loan.interest);
+
+    loan.interest -= interest_paid;
+    loan.debt -= amount - interest_paid;
+
+    interest_paid
+}
+
+/// Books a repayment: the loan tokens are back in the contract as cash.
+/// `penalty` is split between the pool and the treasury. Returns the cash
+/// now held.
+fn return_to_pool(env: &Env, amount: i128, penalty: i128) -> Result<i128, Error> {
+    let treasury: Option<Address> = env.storage().instance().get(&DataKey::Treasury);
+
+    // Without a treasury the lenders keep the whole penalty.
+    let lender_penalty = match treasury {
+        Some(treasury) if penalty > 0 => {
+            let (_, lender_share) = CollateralizedLoanContract::get_penalty_params(env.clone());
+
+            let lender_penalty = mul_div_floor(penalty, lender_share, RATE_SCALE)?;
+
+            let loan_token: Address = env
+                .storage()
+                .instance()
+                .get(&DataKey::LoanToken)
+                .ok_or(Error::NotInitialized)?;
+
+            token::Client::new(env, &loan_token).transfer(
+                &env.current_contract_address(),
+                &treasury,
+                &(penalty - lender_penalty),
+            );
+
+            lender_penalty
+        }
+        _ => penalty,
+    };
+
+    let mut total_cash: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalCash)
+        .unwrap_or(0);
+
+    total_cash = add(total_cash, add(amount, lender_penalty)?)?;
+
+    env.storage()
+        .instance()
+        .set(&DataKey::TotalCash, &total_cash);
+
+    // Accrued interest was already counted in the pool value, so the payment
+    // just turns borrows back into cash. Rounding in the index can leave the
+    // aggregate a little below the sum of the loans.
+    let total_borrows: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::TotalBorrows)
+        .unwrap_or(0);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::TotalBorrows, &(total_borrows - amount).max(0));
+
+    Ok(total_cash)
+}
+
+fn health_factor(env: &Env, loan: &Loan) -> Result<i128, Error> {
+    let outstanding = outstanding(loan)?;
+
+    if outstanding == 0 {
+        return Ok(i128::MAX);
+    }
+
+    let collateral_token: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::CollateralToken)
+        .ok_or(Error::NotInitialized)?;
+
+    let liquidation_threshold: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::LiquidationThreshold)
+        .ok_or(Error::NotInitialized)?;
+
+    let collateral_value = oracle::value_of(env, &collateral_token, loan.amount_locked)?;
+
+    mul_div_floor(collateral_value, liquidation_threshold, outstanding)
+}
+
+/// Principal, interest and penalty still owed on `loan`.
+fn outstanding(loan: &Loan) -> Result<i128, Error> {
+    add(add(loan.debt, loan.interest)?, loan.penalty)
+}
+
+/// Brings interest and late fees of `loan` up to now.
+fn accrue_loan(env: &Env, loan: &mut Loan, borrow_index: i128) -> Result<(), Error> {
+    accrue_loan_interest(loan, borrow_index)?;
+    accrue_penalty(env, loan)
+}
+
+/// Charges the penalty rate on principal overdue since the penalty was last
+/// accrued. What is overdue only changes at due dates, so the time since then
+/// is charged one due date interval at a time.
+fn accrue_penalty(env: &Env, loan: &mut Loan) -> Result<(), Error> {
+    let now = env.ledger().timestamp();
+    let since = loan.penalty_accrued_at;
+
+    loan.penalty_accrued_at = now;
+
+    let (penalty_rate, _) = CollateralizedLoanContract::get_penalty_params(env.clone());
+
+    if penalty_rate == 0 || now <= since {
+        return Ok(());
+    }
+
+    let principal_paid = loan.principal - loan.debt;
+    let installments = loan.due_dates.len();
+
+    for index in 0..installments {
+        let start = loan.due_dates.get(index).unwrap().max(since);
+
+        let end = if index + 1 < installments {
+            loan.due_dates.get(index + 1).unwrap().min(now)
+        } else {
+            now
+        };
+
+        if end <= start {
+            continue;
+        }
+
+        let scheduled = if index + 1 == installments {
+            loan.principal
+        } else {
+            (index as i128 + 1) * loan.installment_amount
+        };
+
+        let overdue = (scheduled - principal_paid).max(0);
+
+        loan.penalty = add(
+            loan.penalty,
+            mul_div_ceil(
+                overdue,
+                mul(penalty_rate, (end - start) as i128)?,
+                RATE_SCALE * SECONDS_PER_DAY as i128,
+            )?,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Moves the interest a loan accrued since it was last touched into
+/// `loan.interest`, rounded up.
+fn accrue_loan_interest(loan: &mut Loan, borrow_index: i128) -> Result<(), Error> {
+    let outstanding = add(loan.debt, loan.interest)?;
+    let accrued = mul_div_ceil(outstanding, borrow_index, loan.borrow_index)?;
+
+    loan.interest = add(loan.interest, sub(accrued, outstanding)?)?;
+    loan.borrow_index = borrow_index;
+
+    Ok(())
+}
+
+fn read_loan(env: &Env, loan_id: u64) -> Result<Loan, Error> {
+    let key = DataKey::Loan(loan_id);
+
+    let loan = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .ok_or(Error::LoanNotFound)?;
+
+    env.storage()
+        .persistent()
+        .bump(&key, LOAN_LIFETIME_THRESHOLD, LOAN_BUMP_AMOUNT);
+
+    Ok(loan)
+}
+
+/// Stores `loan` and, the first time it is written, indexes it under its
+/// borrower.
+fn write_loan(env: &Env, loan: &Loan) {
+    let key = DataKey::Loan(loan.id);
+
+    if !env.storage().persistent().has(&key) {
+        let mut loan_ids = borrower_loans(env, &loan.borrower);
+        loan_ids.push_back(loan.id);
+        write_borrower_loans(env, &loan.borrower, &loan_ids);
+    }
+
+    env.storage().persistent().set(&key, loan);
+    env.storage()
+        .persistent()
+        .bump(&key, LOAN_LIFETIME_THRESHOLD, LOAN_BUMP_AMOUNT);
+}
+
+fn remove_loan(env: &Env, loan: &Loan) {
+    env.storage().persistent().remove(&DataKey::Loan(loan.id));
+
+    let mut loan_ids = borrower_loans(env, &loan.borrower);
+
+    if let Some(index) = loan_ids.first_index_of(loan.id) {
+        loan_ids.remove(index);
+    }
+
+    if loan_ids.is_empty() {
+        env.storage()
+            .persistent()
+            .remove(&DataKey::BorrowerLoans(loan.borrower.clone()));
+    } else {
+        write_borrower_loans(env, &loan.borrower, &loan_ids);
+    }
+}
+
+fn borrower_loans(env: &Env, borrower: &Address) -> Vec<u64> {
+    env.storage()
+        .persistent()
+        .get(&DataKey::BorrowerLoans(borrower.clone()))
+        .unwrap_or(Vec::new(env))
+}
+
+fn write_borrower_loans(env: &Env, borrower: &Address, loan_ids: &Vec<u64>) {
+    let key = DataKey::BorrowerLoans(borrower.clone());
+
+    env.storage().persistent().set(&key, loan_ids);
+    env.storage()
+        .persistent()
+        .bump(&key, LOAN_LIFETIME_THRESHOLD, LOAN_BUMP_AMOUNT);
+}
+
+fn read_shares(env: &Env, lender: &Address) -> i128 {
+    env.storage()
+        .persistent()
+        .get(&DataKey::Shares(lender.clone()))
+        .unwrap_or(0)
+}
+
+/// Lender balances live in persistent storage, like loans, so the instance
+/// does not grow with every lender.
+fn write_shares(env: &Env, lender: &Address, shares: i128) {
+    let key = DataKey::Shares(lender.clone());
+
+    if shares == 0 {
+        env.storage().persistent().remove(&key);
+        return;
+    }
+
+    env.storage().persistent().set(&key, &shares);
+    env.storage()
+        .persistent()
+        .bump(&key, LOAN_LIFETIME_THRESHOLD, LOAN_BUMP_AMOUNT);
 }
 
 #[cfg(test)]
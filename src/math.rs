@@ -0,0 +1,157 @@
+use crate::Error;
+
+/// Scale of rates and ratios: 1000000 is 100%.
+pub const RATE_SCALE: i128 = 1_000_000;
+
+/// Fixed point scale of the borrow index.
+pub const INDEX_SCALE: i128 = 1_000_000_000_000;
+
+// Rounding policy: whatever borrowers owe rounds up, in favour of the pool,
+// and whatever the pool pays out or credits (shares, withdrawals, collateral
+// value and seizures, reserves) rounds down.
+
+pub fn pow10(decimals: u32) -> Result<i128, Error> {
+    10_i128.checked_pow(decimals).ok_or(Error::MathOverflow)
+}
+
+pub fn add(a: i128, b: i128) -> Result<i128, Error> {
+    a.checked_add(b).ok_or(Error::MathOverflow)
+}
+
+pub fn sub(a: i128, b: i128) -> Result<i128, Error> {
+    a.checked_sub(b).ok_or(Error::MathOverflow)
+}
+
+pub fn mul(a: i128, b: i128) -> Result<i128, Error> {
+    a.checked_mul(b).ok_or(Error::MathOverflow)
+}
+
+/// `a * b / c` rounded towards negative infinity.
+pub fn mul_div_floor(a: i128, b: i128, c: i128) -> Result<i128, Error> {
+    let (quotient, inexact, negative) = mul_div(a, b, c)?;
+
+    if inexact && negative {
+        return quotient.checked_sub(1).ok_or(Error::MathOverflow);
+    }
+
+    Ok(quotient)
+}
+
+/// `a * b / c` rounded towards positive infinity.
+pub fn mul_div_ceil(a: i128, b: i128, c: i128) -> Result<i128, Error> {
+    let (quotient, inexact, negative) = mul_div(a, b, c)?;
+
+    if inexact && !negative {
+        return quotient.checked_add(1).ok_or(Error::MathOverflow);
+    }
+
+    Ok(quotient)
+}
+
+/// `a * b / c` truncated, whether it was exact and whether it is negative.
+/// The product is kept in 256 bits, so only a quotient that does not fit in
+/// an `i128` fails.
+fn mul_div(a: i128, b: i128, c: i128) -> Result<(i128, bool, bool), Error> {
+    if c == 0 {
+        return Err(Error::DivisionByZero);
+    }
+
+    let negative = (a < 0) != (b < 0) && a != 0 && b != 0;
+    let negative = negative != (c < 0);
+
+    if let Some(product) = a.checked_mul(b) {
+        // Only `i128::MIN / -1` can overflow here.
+        let quotient = product.checked_div(c).ok_or(Error::MathOverflow)?;
+
+        return Ok((quotient, product % c != 0, negative));
+    }
+
+    let (high, low) = wide_mul(a.unsigned_abs(), b.unsigned_abs());
+    let (quotient, remainder) = wide_div(high, low, c.unsigned_abs())?;
+
+    let quotient = if negative {
+        0_i128.checked_sub_unsigned(quotient)
+    } else {
+        i128::try_from(quotient).ok()
+    };
+
+    Ok((
+        quotient.ok_or(Error::MathOverflow)?,
+        remainder != 0,
+        negative,
+    ))
+}
+
+/// Full 256 bit product of `a` and `b` as its high and low halves.
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+
+    let (a_high, a_low) = (a >> 64, a & MASK);
+    let (b_high, b_low) = (b >> 64, b & MASK);
+
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+
+    let middle = (low_low >> 64) + (low_high & MASK) + (high_low & MASK);
+
+    let low = (low_low & MASK) | (middle << 64);
+    let high = high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+
+    (high, low)
+}
+
+/// Divides the 256 bit number `high:low` by `divisor`, one bit at a time.
+/// Fails if the quotient needs more than 128 bits.
+fn wide_div(high: u128, low: u128, divisor: u128) -> Result<(u128, u128), Error> {
+    if high >= divisor {
+        return Err(Error::MathOverflow);
+    }
+
+    let mut remainder = high;
+    let mut quotient = 0_u128;
+
+    for bit in (0..128).rev() {
+        // The remainder is below the divisor, so doubling it can carry out
+        // of 128 bits at most once and the carry means it is over the divisor.
+        let carry = remainder >> 127;
+
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+
+        if carry == 1 || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1;
+        }
+    }
+
+    Ok((quotient, remainder))
+}
+
+/// Moves `amount` of a token with `from` decimals to `to` decimals, rounding
+/// down.
+pub fn rescale(amount: i128, from: u32, to: u32) -> Result<i128, Error> {
+    if to >= from {
+        mul(amount, pow10(to - from)?)
+    } else {
+        mul_div_floor(amount, 1, pow10(from - to)?)
+    }
+}
+
+/// `amount * numerator / denominator` where `amount` has `from` decimals and
+/// the result `to` decimals, rounded down. Scales up before dividing and down
+/// after, so only the final result is truncated.
+pub fn convert(
+    amount: i128,
+    numerator: i128,
+    denominator: i128,
+    from: u32,
+    to: u32,
+) -> Result<i128, Error> {
+    if to >= from {
+        mul_div_floor(rescale(amount, from, to)?, numerator, denominator)
+    } else {
+        rescale(mul_div_floor(amount, numerator, denominator)?, from, to)
+    }
+}
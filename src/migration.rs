@@ -0,0 +1,137 @@
+use soroban_sdk::{contracttype, token, Address, Env, TryFromVal, Val, Vec};
+
+use crate::{
+    interest, params, write_loan, DataKey, Error, InterestRateModel, Loan, INDEX_SCALE,
+    MAX_INTEREST_RATE,
+};
+
+/// Storage layout written by this version of the contract.
+pub const STORAGE_VERSION: u32 = 1;
+
+/// Borrower record of the first release, which kept every borrower in one
+/// instance vector next to a parallel vector of their addresses.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LegacyBorrower {
+    pub address: Address,
+    pub amount_locked: i128,
+    pub debt: i128,
+}
+
+pub fn read_version(env: &Env) -> u32 {
+    env.storage().instance().get(&DataKey::Version).unwrap_or(0)
+}
+
+pub fn write_version(env: &Env, version: u32) {
+    env.storage().instance().set(&DataKey::Version, &version);
+}
+
+/// Moves a first release contract onto loan records and pool shares. Keys
+/// that are already in the new layout are left alone, so contracts deployed
+/// after the first release but before versioning pass through unchanged.
+///
+/// The first release stored the collateral rate as loan tokens per
+/// collateral unit, which cannot be turned into a share of the collateral
+/// value without a price, so `collateral_rate`, in millionths, replaces it.
+/// Its `InterestRate` was a fee charged once per loan; it is kept as the
+/// annual base rate, capped at `MAX_INTEREST_RATE`, and should be reviewed
+/// after migrating.
+///
+/// The first release kept no record of who lent what, so its lenders cannot
+/// be given shares here. Migration fails while it still holds their cash or
+/// loans that owe them; those have to be settled first.
+pub fn migrate_v1(env: &Env, collateral_rate: i128) -> Result<(), Error> {
+    let storage = env.storage().instance();
+
+    params::check_collateral_rate(env, collateral_rate)?;
+
+    let legacy_cash: i128 = storage.get(&DataKey::TotalSupply).unwrap_or(0);
+
+    let legacy_borrowers: Vec<LegacyBorrower> =
+        storage.get(&DataKey::Borrowers).unwrap_or(Vec::new(env));
+
+    if legacy_cash != 0 || legacy_borrowers.iter().any(|borrower| borrower.debt != 0) {
+        return Err(Error::LegacyPoolNotEmpty);
+    }
+
+    let loan_token: Address = storage
+        .get(&DataKey::LoanToken)
+        .ok_or(Error::NotInitialized)?;
+
+    let collateral_token: Address = storage
+        .get(&DataKey::CollateralToken)
+        .ok_or(Error::NotInitialized)?;
+
+    for token in [&loan_token, &collateral_token] {
+        let key = DataKey::Decimals(token.clone());
+
+        if !storage.has(&key) {
+            storage.set(&key, &token::Client::new(env, token).decimals());
+        }
+    }
+
+    if !storage.has(&DataKey::RateModel) {
+        let interest_rate: i128 = storage.get(&DataKey::InterestRate).unwrap_or(0);
+
+        interest::write_model(
+            env,
+            &InterestRateModel::flat(interest_rate.clamp(0, MAX_INTEREST_RATE)),
+        );
+    }
+
+    storage.remove(&DataKey::InterestRate);
+
+    // The first release stored the installment count as an `i32`.
+    if let Some(installments) = storage.get::<_, Val>(&DataKey::Installments) {
+        if let Ok(installments) = i32::try_from_val(env, &installments) {
+            storage.set(&DataKey::Installments, &(installments.max(1) as u32));
+        }
+    }
+
+    if !storage.has(&DataKey::BorrowIndex) {
+        storage.set(&DataKey::BorrowIndex, &INDEX_SCALE);
+        storage.set(&DataKey::LastAccrual, &env.ledger().timestamp());
+    }
+
+    params::write_collateral_rate(env, collateral_rate);
+
+    let borrow_index: i128 = storage.get(&DataKey::BorrowIndex).unwrap_or(INDEX_SCALE);
+
+    let now = env.ledger().timestamp();
+
+    // Borrowers left with collateral but no debt get an open loan to draw on
+    // or withdraw from.
+    for borrower in legacy_borrowers.iter() {
+        if borrower.amount_locked == 0 {
+            continue;
+        }
+
+        let loan_id: u64 = storage.get(&DataKey::NextLoanId).unwrap_or(0);
+
+        storage.set(&DataKey::NextLoanId, &(loan_id + 1));
+
+        let loan = Loan {
+            id: loan_id,
+            borrower: borrower.address,
+            amount_locked: borrower.amount_locked,
+            principal: 0,
+            debt: 0,
+            interest: 0,
+            borrow_index,
+            installment_amount: 0,
+            penalty: 0,
+            penalty_accrued_at: now,
+            due_dates: Vec::new(env),
+            opened_at: now,
+            due_at: 0,
+        };
+
+        write_loan(env, &loan);
+    }
+
+    storage.remove(&DataKey::TotalSupply);
+    storage.remove(&DataKey::Borrowers);
+    storage.remove(&DataKey::BorrowersAddresses);
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use soroban_sdk::{contractclient, contracttype, Address, Env};
+
+use crate::{math, DataKey, Error};
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PriceData {
+    pub price: i128,
+    pub timestamp: u64,
+}
+
+/// Where prices come from and how far they can be trusted.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OracleConfig {
+    pub address: Address,
+    /// Oldest price, in seconds, the contract still accepts.
+    pub max_age: u64,
+    /// Number of decimals the oracle prices are scaled by.
+    pub decimals: u32,
+}
+
+/// Price feed used to value collateral.
+#[contractclient(name = "PriceOracleClient")]
+pub trait PriceOracle {
+    /// Latest price of one whole `asset` token in whole loan tokens, and the
+    /// ledger timestamp it was published at.
+    fn lastprice(env: Env, asset: Address) -> PriceData;
+}
+
+pub fn read_config(env: &Env) -> Result<OracleConfig, Error> {
+    env.storage()
+        .instance()
+        .get(&DataKey::Oracle)
+        .ok_or(Error::OracleNotConfigured)
+}
+
+pub fn write_config(env: &Env, config: &OracleConfig) {
+    env.storage().instance().set(&DataKey::Oracle, config);
+}
+
+/// A zero `max_age` would reject every price.
+pub fn check_config(config: &OracleConfig) -> Result<(), Error> {
+    if config.max_age == 0 {
+        return Err(Error::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+/// Latest price of `asset` together with the scale it is expressed in.
+/// Fails if the oracle has not published a price recently enough.
+pub fn read_price(env: &Env, asset: &Address) -> Result<(i128, i128), Error> {
+    let config = read_config(env)?;
+
+    let price_data = PriceOracleClient::new(env, &config.address).lastprice(asset);
+
+    if price_data.price <= 0 {
+        return Err(Error::InvalidPrice);
+    }
+
+    let now = env.ledger().timestamp();
+
+    // A price from the future cannot have been observed.
+    if price_data.timestamp > now {
+        return Err(Error::InvalidPrice);
+    }
+
+    if price_data.timestamp.saturating_add(config.max_age) < now {
+        return Err(Error::StalePrice);
+    }
+
+    Ok((price_data.price, math::pow10(config.decimals)?))
+}
+
+/// Decimals of `asset` and of the loan token, as read at initialization.
+fn read_decimals(env: &Env, asset: &Address) -> Result<(u32, u32), Error> {
+    let loan_token: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::LoanToken)
+        .ok_or(Error::NotInitialized)?;
+
+    let decimals = |token: &Address| {
+        env.storage()
+            .instance()
+            .get(&DataKey::Decimals(token.clone()))
+            .ok_or(Error::NotInitialized)
+    };
+
+    Ok((decimals(asset)?, decimals(&loan_token)?))
+}
+
+/// Value of `amount` of `asset` in loan token units.
+pub fn value_of(env: &Env, asset: &Address, amount: i128) -> Result<i128, Error> {
+    let (price, scale) = read_price(env, asset)?;
+    let (asset_decimals, loan_decimals) = read_decimals(env, asset)?;
+
+    math::convert(amount, price, scale, asset_decimals, loan_decimals)
+}
+
+/// Amount of `asset` worth `value` loan token units.
+pub fn amount_for(env: &Env, asset: &Address, value: i128) -> Result<i128, Error> {
+    let (price, scale) = read_price(env, asset)?;
+    let (asset_decimals, loan_decimals) = read_decimals(env, asset)?;
+
+    math::convert(value, scale, price, loan_decimals, asset_decimals)
+}
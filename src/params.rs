@@ -0,0 +1,181 @@
+use soroban_sdk::Env;
+
+use crate::{
+    accrue_interest, interest, math::RATE_SCALE, DataKey, Error, InterestRateModel, SECONDS_PER_DAY,
+};
+
+/// Highest borrow rate, 100% a year.
+pub const MAX_INTEREST_RATE: i128 = RATE_SCALE;
+
+/// Highest liquidation bonus, half the repaid value on top.
+pub const MAX_LIQUIDATION_BONUS: i128 = RATE_SCALE / 2;
+
+/// Longest a loan can stay open past its last due date, a year.
+pub const MAX_GRACE_PERIOD: u64 = 365 * SECONDS_PER_DAY;
+
+/// Most installments a loan can be split into.
+pub const MAX_INSTALLMENTS: u32 = 36;
+
+/// The collateral rate has to stay below the liquidation threshold, or a
+/// loan could be liquidatable as soon as it is drawn.
+pub fn check_collateral_rate(env: &Env, collateral_rate: i128) -> Result<(), Error> {
+    let liquidation_threshold: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::LiquidationThreshold)
+        .unwrap_or(RATE_SCALE);
+
+    if collateral_rate <= 0 || collateral_rate >= liquidation_threshold {
+        return Err(Error::InvalidCollateralRate);
+    }
+
+    Ok(())
+}
+
+/// `interest_rate` becomes the base of the current curve, if there is one,
+/// so the whole curve has to stay within `MAX_INTEREST_RATE`.
+pub fn check_interest_rate(env: &Env, interest_rate: i128) -> Result<(), Error> {
+    if !(0..=MAX_INTEREST_RATE).contains(&interest_rate) {
+        return Err(Error::InvalidInterestRate);
+    }
+
+    match interest::read_model(env) {
+        Ok(model) => check_rate_model(&InterestRateModel {
+            base_rate: interest_rate,
+            ..model
+        }),
+        Err(_) => Ok(()),
+    }
+}
+
+/// The curve peaks at full utilization, where it charges all of the base
+/// rate and both slopes.
+pub fn check_rate_model(model: &InterestRateModel) -> Result<(), Error> {
+    if !model.is_valid() {
+        return Err(Error::InvalidParameter);
+    }
+
+    let max_rate = model
+        .base_rate
+        .checked_add(model.slope_1)
+        .and_then(|rate| rate.checked_add(model.slope_2));
+
+    match max_rate {
+        Some(max_rate) if max_rate <= MAX_INTEREST_RATE => Ok(()),
+        _ => Err(Error::InvalidInterestRate),
+    }
+}
+
+/// Whether `model` charges no more than `current` at any utilization. Moving
+/// the kink can raise the rate on one side of it, so it never counts as a
+/// cut.
+pub fn is_rate_cut(current: &InterestRateModel, model: &InterestRateModel) -> bool {
+    model.base_rate <= current.base_rate
+        && model.slope_1 <= current.slope_1
+        && model.slope_2 <= current.slope_2
+        && model.optimal_utilization == current.optimal_utilization
+}
+
+pub fn check_installments(installments: u32) -> Result<(), Error> {
+    if installments == 0 || installments > MAX_INSTALLMENTS {
+        return Err(Error::InvalidInstallments);
+    }
+
+    Ok(())
+}
+
+/// The threshold has to stay above the collateral rate, or a loan could be
+/// liquidatable as soon as it is drawn.
+pub fn check_liquidation_params(
+    env: &Env,
+    liquidation_threshold: i128,
+    liquidation_bonus: i128,
+) -> Result<(), Error> {
+    if liquidation_threshold <= 0 || liquidation_threshold > RATE_SCALE {
+        return Err(Error::InvalidParameter);
+    }
+
+    if !(0..=MAX_LIQUIDATION_BONUS).contains(&liquidation_bonus) {
+        return Err(Error::InvalidLiquidationBonus);
+    }
+
+    let collateral_rate: i128 = env
+        .storage()
+        .instance()
+        .get(&DataKey::CollateralRate)
+        .unwrap_or(0);
+
+    if liquidation_threshold <= collateral_rate {
+        return Err(Error::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+pub fn check_grace_period(grace_period: u64) -> Result<(), Error> {
+    if grace_period > MAX_GRACE_PERIOD {
+        return Err(Error::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+pub fn check_penalty_params(penalty_rate: i128, lender_share: i128) -> Result<(), Error> {
+    if penalty_rate < 0 || !(0..=RATE_SCALE).contains(&lender_share) {
+        return Err(Error::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+pub fn write_collateral_rate(env: &Env, collateral_rate: i128) {
+    env.storage()
+        .instance()
+        .set(&DataKey::CollateralRate, &collateral_rate);
+}
+
+/// Moves the base of the rate curve. Interest up to now is owed at the old
+/// rate.
+pub fn write_interest_rate(env: &Env, interest_rate: i128) -> Result<(), Error> {
+    let model = InterestRateModel {
+        base_rate: interest_rate,
+        ..interest::read_model(env)?
+    };
+
+    write_rate_model(env, &model)
+}
+
+/// Replaces the rate curve. Interest up to now is owed at the old rates.
+pub fn write_rate_model(env: &Env, model: &InterestRateModel) -> Result<(), Error> {
+    accrue_interest(env)?;
+
+    interest::write_model(env, model);
+
+    Ok(())
+}
+
+pub fn write_liquidation_params(env: &Env, liquidation_threshold: i128, liquidation_bonus: i128) {
+    env.storage()
+        .instance()
+        .set(&DataKey::LiquidationThreshold, &liquidation_threshold);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::LiquidationBonus, &liquidation_bonus);
+}
+
+pub fn write_grace_period(env: &Env, grace_period: u64) {
+    env.storage()
+        .instance()
+        .set(&DataKey::GracePeriod, &grace_period);
+}
+
+pub fn write_penalty_params(env: &Env, penalty_rate: i128, lender_share: i128) {
+    env.storage()
+        .instance()
+        .set(&DataKey::PenaltyRate, &penalty_rate);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::PenaltyLenderShare, &lender_share);
+}
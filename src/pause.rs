@@ -0,0 +1,66 @@
+use soroban_sdk::{contracttype, Env};
+
+use crate::{DataKey, Error};
+
+/// Actions that can be stopped independently.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Supply,
+    Borrow,
+    Repay,
+    Withdraw,
+    Liquidate,
+}
+
+/// Which actions are currently stopped.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PauseState {
+    pub supply: bool,
+    pub borrow: bool,
+    pub repay: bool,
+    pub withdraw: bool,
+    pub liquidate: bool,
+}
+
+impl PauseState {
+    pub fn is_paused(&self, action: Action) -> bool {
+        match action {
+            Action::Supply => self.supply,
+            Action::Borrow => self.borrow,
+            Action::Repay => self.repay,
+            Action::Withdraw => self.withdraw,
+            Action::Liquidate => self.liquidate,
+        }
+    }
+
+    pub fn set(&mut self, action: Action, paused: bool) {
+        match action {
+            Action::Supply => self.supply = paused,
+            Action::Borrow => self.borrow = paused,
+            Action::Repay => self.repay = paused,
+            Action::Withdraw => self.withdraw = paused,
+            Action::Liquidate => self.liquidate = paused,
+        }
+    }
+}
+
+pub fn read_state(env: &Env) -> PauseState {
+    env.storage()
+        .instance()
+        .get(&DataKey::PauseState)
+        .unwrap_or_default()
+}
+
+pub fn write_state(env: &Env, state: &PauseState) {
+    env.storage().instance().set(&DataKey::PauseState, state);
+}
+
+pub fn require_not_paused(env: &Env, action: Action) -> Result<(), Error> {
+    if read_state(env).is_paused(action) {
+        return Err(Error::Paused);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use soroban_sdk::{contracttype, Address, Env};
+
+use crate::{DataKey, Error};
+
+/// What an address is allowed to change.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    /// Roles, tokens, treasury, upgrades. Held by the single admin address.
+    Admin,
+    /// Rates, ratios, limits and the oracle.
+    RiskManager,
+    /// Pause flags.
+    PauseGuardian,
+    /// Reserve claims.
+    Treasurer,
+}
+
+/// The admin holds every role, the others are granted one by one.
+pub fn has_role(env: &Env, role: Role, account: &Address) -> bool {
+    let admin: Option<Address> = env.storage().instance().get(&DataKey::Admin);
+
+    if admin.as_ref() == Some(account) {
+        return true;
+    }
+
+    role != Role::Admin
+        && env
+            .storage()
+            .instance()
+            .has(&DataKey::Role(role, account.clone()))
+}
+
+/// Checks that `caller` signed the call and holds `role`.
+pub fn require_role(env: &Env, role: Role, caller: &Address) -> Result<(), Error> {
+    caller.require_auth();
+
+    if !has_role(env, role, caller) {
+        return Err(Error::Unauthorized);
+    }
+
+    Ok(())
+}
+
+pub fn grant(env: &Env, role: Role, account: &Address) {
+    env.storage()
+        .instance()
+        .set(&DataKey::Role(role, account.clone()), &true);
+}
+
+pub fn revoke(env: &Env, role: Role, account: &Address) {
+    env.storage()
+        .instance()
+        .remove(&DataKey::Role(role, account.clone()));
+}
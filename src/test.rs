@@ -1,7 +1,18 @@
 extern crate std;
 
-use crate::{CollateralizedLoanContract, CollateralizedLoanContractClient};
-use soroban_sdk::{testutils::Address as _, token, Address, Env};
+use crate::{
+    math::{add, mul_div_ceil, mul_div_floor, pow10, rescale, sub},
+    Action, CollateralizedLoanContract, CollateralizedLoanContractClient, DataKey, Error,
+    Installment, InterestRateModel, LegacyBorrower, Loan, LoanEvent, OracleConfig, ParamChange,
+    PauseState, PendingChange, PoolEvent, PoolState, PriceData, Role, INDEX_SCALE,
+    INSTALLMENT_PERIOD, MAX_GRACE_PERIOD, MAX_INSTALLMENTS, MAX_INTEREST_RATE,
+    MAX_LIQUIDATION_BONUS, SECONDS_PER_DAY, SECONDS_PER_YEAR, STORAGE_VERSION, TIMELOCK_DELAY,
+};
+use soroban_sdk::{
+    contract, contractimpl, symbol_short,
+    testutils::{Address as _, AuthorizedFunction, AuthorizedInvocation, Events, Ledger},
+    token, vec, Address, BytesN, Env, IntoVal, Symbol, Val, Vec,
+};
 
 use token::Client as TokenClient;
 use token::StellarAssetClient as TokenAdminClient;
@@ -14,6 +25,99 @@ fn create_token_contract<'a>(e: &Env, admin: &Address) -> (TokenClient<'a>, Toke
     )
 }
 
+const PRICE_SCALE: i128 = 10_000_000;
+
+#[contract]
+pub struct MockOracle;
+
+#[contractimpl]
+impl MockOracle {
+    pub fn set_price(env: Env, asset: Address, price: i128) {
+        let price_data = PriceData {
+            price,
+            timestamp: env.ledger().timestamp(),
+        };
+
+        env.storage().instance().set(&asset, &price_data);
+    }
+
+    pub fn lastprice(env: Env, asset: Address) -> PriceData {
+        env.storage().instance().get(&asset).unwrap()
+    }
+}
+
+/// Bare token with configurable decimals, for pools whose tokens are not
+/// scaled like Stellar assets.
+#[contract]
+pub struct MockToken;
+
+#[contractimpl]
+impl MockToken {
+    pub fn init(env: Env, decimals: u32) {
+        env.storage()
+            .instance()
+            .set(&symbol_short!("decimals"), &decimals);
+    }
+
+    pub fn decimals(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&symbol_short!("decimals"))
+            .unwrap()
+    }
+
+    pub fn mint(env: Env, to: Address, amount: i128) {
+        let balance = Self::balance(env.clone(), to.clone());
+        env.storage().instance().set(&to, &(balance + amount));
+    }
+
+    pub fn balance(env: Env, id: Address) -> i128 {
+        env.storage().instance().get(&id).unwrap_or(0)
+    }
+
+    pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+        from.require_auth();
+
+        let from_balance = Self::balance(env.clone(), from.clone());
+        assert!(from_balance >= amount, "insufficient balance");
+
+        env.storage()
+            .instance()
+            .set(&from, &(from_balance - amount));
+        Self::mint(env, to, amount);
+    }
+}
+
+fn create_mock_token<'a>(e: &Env, decimals: u32) -> MockTokenClient<'a> {
+    let token = MockTokenClient::new(e, &e.register_contract(None, MockToken));
+    token.init(&decimals);
+    token
+}
+
+fn last_event(env: &Env) -> Vec<(Address, Vec<Val>, Val)> {
+    let events = env.events().all();
+    events.slice(events.len() - 1..)
+}
+
+fn create_oracle<'a>(e: &Env) -> MockOracleClient<'a> {
+    MockOracleClient::new(e, &e.register_contract(None, MockOracle))
+}
+
+/// Prices the collateral at 5000 loan tokens through a mock oracle that
+/// tolerates prices up to an hour old.
+fn setup_oracle<'a>(
+    e: &Env,
+    client: &CollateralizedLoanContractClient,
+    collateral_token: &Address,
+) -> MockOracleClient<'a> {
+    let oracle = create_oracle(e);
+    oracle.set_price(collateral_token, &(5000 * PRICE_SCALE));
+
+    client.set_oracle(&client.read_administrator(), &oracle.address, &3600, &7);
+
+    oracle
+}
+
 fn initialize_tokens<'a>(
     env: Env,
 ) -> (
@@ -59,16 +163,45 @@ fn initialize() {
     let (token_collateral, _) = create_token_contract(&env, &token_collateral_admin);
     // token_loan_admin.mint(&lender, &10000000);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     client.initialize(
+        &admin,
         &token_loan.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
+    );
+
+    assert_eq!(
+        env.auths(),
+        std::vec![(
+            admin.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    contract_id.clone(),
+                    Symbol::new(&env, "initialize"),
+                    (
+                        admin.clone(),
+                        token_loan.address.clone(),
+                        token_collateral.address.clone(),
+                        collateral_rate,
+                        15000_i128,
+                        3_u32,
+                    )
+                        .into_val(&env),
+                )),
+                sub_invocations: std::vec![],
+            }
+        )]
     );
 
+    assert_eq!(client.read_administrator(), admin);
     assert_eq!(client.get_collateral_token(), token_collateral.address);
     assert_eq!(client.get_loan_token(), token_loan.address);
 }
@@ -92,14 +225,19 @@ fn supply_loan_tokens() {
 
     let (token_lender_collateral, _) = create_token_contract(&env, &token_collateral_admin);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     client.initialize(
+        &admin,
         &token_lender_loan.address,
         &token_lender_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
     let amount_to_supply = 400000;
@@ -126,18 +264,25 @@ fn request_loan() {
     token_lend_admin.mint(&lender, &500000);
     token_collateral_admin.mint(&borrower, &100);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     let amount_to_collateralize: i128 = 100;
 
     client.initialize(
+        &admin,
         &token_to_lend.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
+    setup_oracle(&env, &client, &token_collateral.address);
+
     let amount_to_supply = 500000;
 
     client.supply_loan_tokens(&lender, &amount_to_supply);
@@ -166,34 +311,42 @@ fn get_debt() {
     token_lend_admin.mint(&lender, &500000);
     token_collateral_admin.mint(&borrower, &100);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     let amount_to_collateralize: i128 = 100;
 
     client.initialize(
+        &admin,
         &token_to_lend.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
+    setup_oracle(&env, &client, &token_collateral.address);
+
     let amount_to_supply = 500000;
 
     client.supply_loan_tokens(&lender, &amount_to_supply);
 
-    client.request_loan(&borrower, &amount_to_collateralize);
+    let loan_id = client.request_loan(&borrower, &amount_to_collateralize);
 
-    // let interest_rate = 15000;
-    let interest_rate: i128 = 0;
+    let interest_rate: i128 = 15000;
 
-    // let interest_rate = 0;
+    let loan_amount = 400000;
 
-    let loan_amount = 100 * collateral_rate;
+    assert_eq!(client.get_debt(&loan_id), loan_amount);
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
 
     let total_amount_to_pay = loan_amount + (loan_amount * interest_rate / 1000000);
 
-    assert_eq!(client.get_debt(&borrower), total_amount_to_pay);
+    assert_eq!(client.get_debt(&loan_id), total_amount_to_pay);
 }
 
 #[test]
@@ -212,16 +365,23 @@ fn get_loan_amount() {
     token_lend_admin.mint(&lender, &500000);
     token_collateral_admin.mint(&borrower, &100);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     client.initialize(
+        &admin,
         &token_to_lend.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
+    setup_oracle(&env, &client, &token_collateral.address);
+
     let amount_to_supply = 500000;
 
     client.supply_loan_tokens(&lender, &amount_to_supply);
@@ -242,30 +402,37 @@ fn repay_loan() {
     let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
         initialize_tokens(env.clone());
 
-    let interest_to_pay = 0;
+    let interest_to_pay = 6000;
 
     token_lend_admin.mint(&lender, &400000);
     token_lend_admin.mint(&borrower, &interest_to_pay);
 
     token_collateral_admin.mint(&borrower, &100);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     let amount_to_collateralize: i128 = 100;
 
     client.initialize(
+        &admin,
         &token_to_lend.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
+    setup_oracle(&env, &client, &token_collateral.address);
+
     let amount_to_supply = 400000;
 
     client.supply_loan_tokens(&lender, &amount_to_supply);
 
-    client.request_loan(&borrower, &amount_to_collateralize);
+    let loan_id = client.request_loan(&borrower, &amount_to_collateralize);
 
     // client.deposit_collateral(&borrower, &amount_to_collateralize);
 
@@ -273,20 +440,21 @@ fn repay_loan() {
 
     // client.issue_loan(&lender, &amount_to_lend);
 
-    // let interest_rate = 15000;
-    let interest_rate: i128 = 0;
+    let interest_rate: i128 = 15000;
 
-    let loan_amount = 100 * collateral_rate;
+    let loan_amount = 400000;
 
     let amount_to_pay = loan_amount + (loan_amount * interest_rate / 1000000);
 
-    client.repay_loan(&borrower, &amount_to_pay);
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    client.repay_loan(&loan_id, &amount_to_pay);
 
     assert_eq!(token_to_lend.balance(&contract_id), amount_to_pay);
     assert_eq!(token_to_lend.balance(&borrower), 0);
     assert_eq!(token_collateral.balance(&borrower), 100);
     assert_eq!(token_collateral.balance(&contract_id), 0);
-    assert_eq!(client.get_borrowers().len(), 0);
+    assert_eq!(client.list_loans(&0, &10).0.len(), 0);
 }
 
 #[test]
@@ -302,30 +470,37 @@ fn request_loan_after_repay() {
     let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
         initialize_tokens(env.clone());
 
-    let interest_to_pay = 0;
+    let interest_to_pay = 6000;
 
     token_lend_admin.mint(&lender, &400000);
     token_lend_admin.mint(&borrower, &interest_to_pay);
 
     token_collateral_admin.mint(&borrower, &100);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     let amount_to_collateralize: i128 = 100;
 
     client.initialize(
+        &admin,
         &token_to_lend.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
     let amount_to_supply = 400000;
 
     client.supply_loan_tokens(&lender, &amount_to_supply);
 
-    client.request_loan(&borrower, &amount_to_collateralize);
+    let loan_id = client.request_loan(&borrower, &amount_to_collateralize);
 
     // client.deposit_collateral(&borrower, &amount_to_collateralize);
 
@@ -333,32 +508,35 @@ fn request_loan_after_repay() {
 
     // client.issue_loan(&lender, &amount_to_lend);
 
-    // let interest_rate = 15000;
-    let interest_rate: i128 = 0;
+    let interest_rate: i128 = 15000;
 
-    let loan_amount = 100 * collateral_rate;
+    let loan_amount = 400000;
 
     let amount_to_pay = loan_amount + (loan_amount * interest_rate / 1000000);
 
-    client.repay_loan(&borrower, &amount_to_pay);
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    client.repay_loan(&loan_id, &amount_to_pay);
 
     assert_eq!(token_to_lend.balance(&contract_id), amount_to_pay);
     assert_eq!(token_to_lend.balance(&borrower), 0);
     assert_eq!(token_collateral.balance(&borrower), 100);
     assert_eq!(token_collateral.balance(&contract_id), 0);
-    assert_eq!(client.get_borrowers().len(), 0);
+    assert_eq!(client.list_loans(&0, &10).0.len(), 0);
+
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
 
     client.request_loan(&borrower, &amount_to_collateralize);
 
-    assert_eq!(token_to_lend.balance(&contract_id), 0);
+    assert_eq!(token_to_lend.balance(&contract_id), interest_to_pay);
     assert_eq!(token_to_lend.balance(&borrower), 400000);
     assert_eq!(token_collateral.balance(&borrower), 0);
     assert_eq!(token_collateral.balance(&contract_id), 100);
-    assert_eq!(client.get_borrowers().len(), 1);
+    assert_eq!(client.list_loans(&0, &10).0.len(), 1);
 }
 
 #[test]
-fn get_borrowers() {
+fn list_loans() {
     let env = Env::default();
     env.mock_all_auths();
 
@@ -370,25 +548,32 @@ fn get_borrowers() {
     let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
         initialize_tokens(env.clone());
 
-    let interest_to_pay = 0;
+    let interest_to_pay = 6000;
 
     token_lend_admin.mint(&lender, &400000);
     token_lend_admin.mint(&borrower, &interest_to_pay);
 
     token_collateral_admin.mint(&borrower, &100);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     let amount_to_collateralize: i128 = 100;
 
     client.initialize(
+        &admin,
         &token_to_lend.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
+    setup_oracle(&env, &client, &token_collateral.address);
+
     let amount_to_supply = 400000;
 
     client.supply_loan_tokens(&lender, &amount_to_supply);
@@ -403,7 +588,82 @@ fn get_borrowers() {
 
     // let interest_rate = 15000;
 
-    assert_eq!(client.get_borrowers().first().unwrap().address, borrower);
+    assert_eq!(
+        client.list_loans(&0, &10).0.first().unwrap().borrower,
+        borrower
+    );
+}
+
+#[test]
+fn list_loans_is_paginated() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &1000000);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &1000000);
+
+    let mut borrowers = std::vec::Vec::new();
+
+    for _ in 0..4 {
+        let borrower = Address::random(&env);
+        token_collateral_admin.mint(&borrower, &50);
+        client.request_loan(&borrower, &50);
+        borrowers.push(borrower);
+    }
+
+    let (first_page, next) = client.list_loans(&0, &3);
+
+    assert_eq!(first_page.len(), 3);
+    assert_eq!(first_page.get(0).unwrap().borrower, borrowers[0]);
+    assert_eq!(first_page.get(2).unwrap().borrower, borrowers[2]);
+    assert_eq!(next, Some(3));
+
+    let (second_page, next) = client.list_loans(&3, &3);
+
+    assert_eq!(second_page.len(), 1);
+    assert_eq!(second_page.get(0).unwrap().borrower, borrowers[3]);
+    assert_eq!(next, None);
+
+    // Closed loans drop out of the listing and out of their borrower's index.
+    token_lend_admin.mint(&borrowers[1], &200000);
+    client.repay_loan(&1, &200000);
+
+    let (loans, _) = client.list_loans(&0, &10);
+
+    assert_eq!(loans.len(), 3);
+    assert_eq!(loans.get(1).unwrap().id, 2);
+
+    // A page only scans `limit` ids, so a closed loan leaves a gap in it.
+    let (page, next) = client.list_loans(&0, &2);
+
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get(0).unwrap().id, 0);
+    assert_eq!(next, Some(2));
+    assert_eq!(client.get_loans_of(&borrowers[1]).len(), 0);
+    assert_eq!(client.try_get_loan(&1), Err(Ok(Error::LoanNotFound)));
 }
 
 #[test]
@@ -419,21 +679,26 @@ fn get_total_supply() {
     let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
         initialize_tokens(env.clone());
 
-    let interest_to_pay = 0;
+    let interest_to_pay = 6000;
 
     token_lend_admin.mint(&lender, &400000);
     token_lend_admin.mint(&borrower, &interest_to_pay);
 
     token_collateral_admin.mint(&borrower, &100);
 
+    let admin = Address::random(&env);
+
     let client = CollateralizedLoanContractClient::new(&env, &contract_id);
 
-    let collateral_rate: i128 = 4000;
+    let collateral_rate: i128 = 800000;
 
     client.initialize(
+        &admin,
         &token_to_lend.address,
         &token_collateral.address,
         &collateral_rate,
+        &15000,
+        &3,
     );
 
     let amount_to_supply = 400000;
@@ -442,3 +707,4079 @@ fn get_total_supply() {
 
     assert_eq!(client.get_total_supply(), 400000);
 }
+
+#[test]
+fn supply_loan_tokens_mints_shares() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let other_lender = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, _) = initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&other_lender, &100000);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    assert_eq!(client.supply_loan_tokens(&lender, &400000), 400000);
+    assert_eq!(client.supply_loan_tokens(&other_lender, &100000), 100000);
+
+    assert_eq!(client.get_shares(&lender), 400000);
+    assert_eq!(client.get_shares(&other_lender), 100000);
+    assert_eq!(client.get_total_shares(), 500000);
+
+    // Balances are kept per lender outside the instance.
+    env.as_contract(&contract_id, || {
+        let key = DataKey::Shares(other_lender.clone());
+
+        assert!(env.storage().persistent().has(&key));
+        assert!(!env.storage().instance().has(&key));
+    });
+}
+
+#[test]
+fn withdraw_loan_tokens() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, _) = initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    assert_eq!(client.withdraw_loan_tokens(&lender, &100000), 100000);
+
+    assert_eq!(token_to_lend.balance(&lender), 100000);
+    assert_eq!(token_to_lend.balance(&contract_id), 300000);
+    assert_eq!(client.get_shares(&lender), 300000);
+    assert_eq!(client.get_total_shares(), 300000);
+    assert_eq!(client.get_total_supply(), 300000);
+
+    assert_eq!(client.withdraw_loan_tokens(&lender, &300000), 300000);
+
+    assert_eq!(token_to_lend.balance(&lender), 400000);
+    assert_eq!(client.get_shares(&lender), 0);
+    assert_eq!(client.get_total_shares(), 0);
+}
+
+#[test]
+fn repaid_interest_raises_share_value() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let late_lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&late_lender, &406000);
+    token_lend_admin.mint(&borrower, &6000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    // Lent out principal still belongs to the lender.
+    assert_eq!(client.get_shares_value(&400000), 400000);
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    // Interest counts towards the shares as soon as it accrues.
+    assert_eq!(client.get_shares_value(&400000), 406000);
+
+    client.repay_loan(&loan_id, &406000);
+
+    assert_eq!(client.get_shares_value(&400000), 406000);
+
+    // Someone joining after the interest was paid gets fewer shares per token.
+    assert_eq!(client.supply_loan_tokens(&late_lender, &406000), 400000);
+
+    assert_eq!(client.withdraw_loan_tokens(&lender, &400000), 406000);
+    assert_eq!(token_to_lend.balance(&lender), 406000);
+}
+
+#[test]
+fn request_multiple_loans() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let other_borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &1000000);
+    token_collateral_admin.mint(&borrower, &150);
+    token_collateral_admin.mint(&other_borrower, &50);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &1000000);
+
+    let first_loan = client.request_loan(&borrower, &100);
+    let other_loan = client.request_loan(&other_borrower, &50);
+    let second_loan = client.request_loan(&borrower, &50);
+
+    assert_eq!(first_loan, 0);
+    assert_eq!(other_loan, 1);
+    assert_eq!(second_loan, 2);
+
+    assert_eq!(
+        client.get_loan(&second_loan),
+        Loan {
+            id: second_loan,
+            borrower: borrower.clone(),
+            amount_locked: 50,
+            principal: 200000,
+            debt: 200000,
+            interest: 0,
+            borrow_index: INDEX_SCALE,
+            installment_amount: 66666,
+            penalty: 0,
+            penalty_accrued_at: 0,
+            due_dates: vec![
+                &env,
+                INSTALLMENT_PERIOD,
+                2 * INSTALLMENT_PERIOD,
+                3 * INSTALLMENT_PERIOD
+            ],
+            opened_at: 0,
+            due_at: 3 * INSTALLMENT_PERIOD,
+        }
+    );
+
+    let loans = client.get_loans_of(&borrower);
+
+    assert_eq!(loans.len(), 2);
+    assert_eq!(loans.get(0).unwrap().id, first_loan);
+    assert_eq!(loans.get(1).unwrap().id, second_loan);
+
+    assert_eq!(client.get_debt(&first_loan), 400000);
+    assert_eq!(client.get_debt(&second_loan), 200000);
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    assert_eq!(client.get_debt(&first_loan), 406000);
+    assert_eq!(client.get_debt(&second_loan), 203000);
+    assert_eq!(token_to_lend.balance(&borrower), 600000);
+}
+
+#[test]
+fn repay_one_of_multiple_loans() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &1000000);
+    token_lend_admin.mint(&borrower, &3000);
+    token_collateral_admin.mint(&borrower, &150);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &1000000);
+
+    let first_loan = client.request_loan(&borrower, &100);
+    let second_loan = client.request_loan(&borrower, &50);
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    client.repay_loan(&second_loan, &203000);
+
+    let loans = client.get_loans_of(&borrower);
+
+    assert_eq!(loans.len(), 1);
+    assert_eq!(loans.get(0).unwrap().id, first_loan);
+    assert_eq!(token_collateral.balance(&borrower), 50);
+    assert_eq!(token_collateral.balance(&contract_id), 100);
+    assert_eq!(token_to_lend.balance(&borrower), 400000);
+}
+
+#[test]
+fn repay_loan_in_installments() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| li.timestamp = 1000);
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&borrower, &494);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    // 400000 split over three installments, the last one takes the remainder.
+    assert_eq!(
+        client.get_next_installment(&loan_id),
+        Installment {
+            due_date: 1000 + INSTALLMENT_PERIOD,
+            amount: 133333,
+        }
+    );
+
+    env.ledger()
+        .with_mut(|li| li.timestamp += INSTALLMENT_PERIOD);
+
+    // 30 days of 1.5% APR on 400000 is due with the first installment.
+    assert_eq!(
+        client.get_next_installment(&loan_id),
+        Installment {
+            due_date: 1000 + INSTALLMENT_PERIOD,
+            amount: 133827,
+        }
+    );
+
+    assert_eq!(client.repay_loan(&loan_id, &133827), 266667);
+
+    // Interest is settled before principal.
+    let loan = client.get_loan(&loan_id);
+    assert_eq!(loan.interest, 0);
+    assert_eq!(loan.debt, 266667);
+
+    assert_eq!(
+        client.get_next_installment(&loan_id),
+        Installment {
+            due_date: 1000 + 2 * INSTALLMENT_PERIOD,
+            amount: 133333,
+        }
+    );
+
+    // A smaller payment leaves the rest of the same installment due.
+    assert_eq!(client.repay_loan(&loan_id, &100000), 166667);
+
+    assert_eq!(
+        client.get_next_installment(&loan_id),
+        Installment {
+            due_date: 1000 + 2 * INSTALLMENT_PERIOD,
+            amount: 33333,
+        }
+    );
+
+    assert_eq!(client.repay_loan(&loan_id, &33333), 133334);
+
+    // Collateral stays locked until the last installment is paid.
+    assert_eq!(token_collateral.balance(&contract_id), 100);
+    assert_eq!(client.list_loans(&0, &10).0.len(), 1);
+
+    assert_eq!(
+        client.get_next_installment(&loan_id),
+        Installment {
+            due_date: 1000 + 3 * INSTALLMENT_PERIOD,
+            amount: 133334,
+        }
+    );
+
+    assert_eq!(client.repay_loan(&loan_id, &133334), 0);
+
+    assert_eq!(token_collateral.balance(&borrower), 100);
+    assert_eq!(token_collateral.balance(&contract_id), 0);
+    assert_eq!(token_to_lend.balance(&contract_id), 400494);
+    assert_eq!(client.get_total_supply(), 400494);
+    assert_eq!(client.list_loans(&0, &10).0.len(), 0);
+}
+
+#[test]
+fn interest_accrues_over_time() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &500000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    assert_eq!(client.get_debt(&loan_id), 400000);
+
+    env.ledger()
+        .with_mut(|li| li.timestamp += SECONDS_PER_YEAR / 2);
+
+    assert_eq!(client.get_debt(&loan_id), 403000);
+
+    // The loan view is accrued too, though nothing was written.
+    let loan = client.get_loan(&loan_id);
+
+    assert_eq!((loan.debt, loan.interest), (400000, 3000));
+
+    // Any state change accrues into the index, so the second half of the
+    // year compounds on the first.
+    client.supply_loan_tokens(&lender, &100000);
+
+    env.ledger()
+        .with_mut(|li| li.timestamp += SECONDS_PER_YEAR / 2);
+
+    assert_eq!(client.get_debt(&loan_id), 406023);
+}
+
+#[test]
+fn liquidate_undercollateralized_loan() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let liquidator = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&liquidator, &200000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    assert_eq!(client.get_health_factor(&loan_id), 1062500);
+
+    oracle.set_price(&token_collateral.address, &(4000 * PRICE_SCALE));
+
+    assert_eq!(client.get_health_factor(&loan_id), 850000);
+
+    // Half the debt buys 200000 / 4000 collateral plus the 5% bonus.
+    assert_eq!(client.liquidate(&liquidator, &loan_id, &200000), 52);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("liquidate"),
+                    borrower.clone(),
+                    liquidator.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 52,
+                    principal: 200000,
+                    interest: 0,
+                    penalty: 0,
+                    total_cash: 200000,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    let loan = client.get_loan(&loan_id);
+    assert_eq!(loan.debt, 200000);
+    assert_eq!(loan.amount_locked, 48);
+
+    assert_eq!(token_collateral.balance(&liquidator), 52);
+    assert_eq!(token_collateral.balance(&contract_id), 48);
+    assert_eq!(token_to_lend.balance(&liquidator), 0);
+    assert_eq!(client.get_total_supply(), 200000);
+}
+
+#[test]
+fn liquidate_whole_loan() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let liquidator = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&liquidator, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    oracle.set_price(&token_collateral.address, &(4000 * PRICE_SCALE));
+
+    // The bonus would exceed what is locked, so the liquidator gets all of it.
+    assert_eq!(client.liquidate(&liquidator, &loan_id, &400000), 100);
+
+    assert_eq!(token_collateral.balance(&liquidator), 100);
+    assert_eq!(token_collateral.balance(&contract_id), 0);
+    assert_eq!(client.list_loans(&0, &10).0.len(), 0);
+    assert_eq!(client.get_total_supply(), 400000);
+}
+
+#[test]
+fn get_loan_amount_follows_oracle_price() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(
+        client.get_oracle(),
+        OracleConfig {
+            address: oracle.address.clone(),
+            max_age: 3600,
+            decimals: 7,
+        }
+    );
+
+    assert_eq!(client.get_loan_amount(&100), 400000);
+
+    oracle.set_price(
+        &token_collateral.address,
+        &(2500 * PRICE_SCALE + PRICE_SCALE / 2),
+    );
+
+    assert_eq!(client.get_loan_amount(&100), 200040);
+
+    // A price exactly as old as the allowed age is still accepted.
+    env.ledger().with_mut(|li| li.timestamp += 3600);
+
+    assert_eq!(client.get_loan_amount(&100), 200040);
+}
+
+#[test]
+fn initialize_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    assert_eq!(client.try_get_loan_token(), Err(Ok(Error::NotInitialized)));
+    assert_eq!(
+        client.try_read_administrator(),
+        Err(Ok(Error::NotInitialized))
+    );
+
+    assert_eq!(
+        client.try_initialize(
+            &admin,
+            &token_to_lend.address,
+            &token_collateral.address,
+            &0,
+            &15000,
+            &3
+        ),
+        Err(Ok(Error::InvalidCollateralRate))
+    );
+    assert_eq!(
+        client.try_initialize(
+            &admin,
+            &token_to_lend.address,
+            &token_collateral.address,
+            &1000000,
+            &15000,
+            &3
+        ),
+        Err(Ok(Error::InvalidCollateralRate))
+    );
+    assert_eq!(
+        client.try_initialize(
+            &admin,
+            &token_to_lend.address,
+            &token_collateral.address,
+            &800000,
+            &-1,
+            &3
+        ),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+    assert_eq!(
+        client.try_initialize(
+            &admin,
+            &token_to_lend.address,
+            &token_collateral.address,
+            &800000,
+            &10000000,
+            &3
+        ),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+    assert_eq!(
+        client.try_initialize(
+            &admin,
+            &token_to_lend.address,
+            &token_collateral.address,
+            &800000,
+            &15000,
+            &0
+        ),
+        Err(Ok(Error::InvalidInstallments))
+    );
+    assert_eq!(
+        client.try_initialize(
+            &admin,
+            &token_to_lend.address,
+            &token_collateral.address,
+            &800000,
+            &15000,
+            &10000
+        ),
+        Err(Ok(Error::InvalidInstallments))
+    );
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    assert_eq!(
+        client.try_initialize(
+            &admin,
+            &token_to_lend.address,
+            &token_collateral.address,
+            &800000,
+            &15000,
+            &3
+        ),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+
+    assert_eq!(client.try_get_oracle(), Err(Ok(Error::OracleNotConfigured)));
+    assert_eq!(
+        client.try_get_loan_amount(&100),
+        Err(Ok(Error::OracleNotConfigured))
+    );
+}
+
+#[test]
+fn request_loan_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &300000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &300000);
+
+    assert_eq!(
+        client.try_request_loan(&borrower, &0),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_request_loan(&borrower, &100),
+        Err(Ok(Error::InsufficientLiquidity))
+    );
+
+    oracle.set_price(&token_collateral.address, &0);
+
+    assert_eq!(
+        client.try_request_loan(&borrower, &50),
+        Err(Ok(Error::InvalidPrice))
+    );
+
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
+
+    env.ledger().with_mut(|li| li.timestamp += 3601);
+
+    assert_eq!(
+        client.try_request_loan(&borrower, &50),
+        Err(Ok(Error::StalePrice))
+    );
+
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
+
+    env.ledger().with_mut(|li| li.timestamp -= 1);
+
+    assert_eq!(
+        client.try_request_loan(&borrower, &50),
+        Err(Ok(Error::InvalidPrice))
+    );
+
+    assert_eq!(
+        client.try_set_oracle(&admin, &oracle.address, &0, &7),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    // However old the oracle lets prices get, the deadline cannot overflow.
+    client.set_oracle(&admin, &oracle.address, &u64::MAX, &7);
+
+    env.ledger().with_mut(|li| li.timestamp += 3601);
+
+    assert_eq!(client.get_loan_amount(&1), 4000);
+
+    // Nothing moved on any of the failed attempts.
+    assert_eq!(token_collateral.balance(&borrower), 100);
+    assert_eq!(client.get_total_supply(), 300000);
+}
+
+#[test]
+fn supply_and_withdraw_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(
+        client.try_supply_loan_tokens(&lender, &0),
+        Err(Ok(Error::InvalidAmount))
+    );
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    assert_eq!(
+        client.try_withdraw_loan_tokens(&lender, &0),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_withdraw_loan_tokens(&lender, &400001),
+        Err(Ok(Error::InsufficientShares))
+    );
+
+    client.request_loan(&borrower, &50);
+
+    // Half the pool is lent out and cannot be withdrawn yet.
+    assert_eq!(
+        client.try_withdraw_loan_tokens(&lender, &200001),
+        Err(Ok(Error::InsufficientLiquidity))
+    );
+    assert_eq!(client.withdraw_loan_tokens(&lender, &200000), 200000);
+}
+
+#[test]
+fn repay_loan_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    assert_eq!(
+        client.try_repay_loan(&(loan_id + 1), &1000),
+        Err(Ok(Error::LoanNotFound))
+    );
+    assert_eq!(
+        client.try_repay_loan(&loan_id, &0),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_repay_loan(&loan_id, &400001),
+        Err(Ok(Error::AmountExceedsDebt))
+    );
+    assert_eq!(
+        client.try_get_debt(&(loan_id + 1)),
+        Err(Ok(Error::LoanNotFound))
+    );
+    assert_eq!(
+        client.try_get_next_installment(&(loan_id + 1)),
+        Err(Ok(Error::LoanNotFound))
+    );
+}
+
+#[test]
+fn liquidate_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let liquidator = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&liquidator, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(
+        client.try_set_liquidation_params(&admin, &0, &50000),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_set_liquidation_params(&admin, &1000001, &50000),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_set_liquidation_params(&admin, &850000, &-1),
+        Err(Ok(Error::InvalidLiquidationBonus))
+    );
+    assert_eq!(
+        client.try_set_liquidation_params(&admin, &850000, &(MAX_LIQUIDATION_BONUS + 1)),
+        Err(Ok(Error::InvalidLiquidationBonus))
+    );
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    assert_eq!(
+        client.try_liquidate(&liquidator, &loan_id, &200000),
+        Err(Ok(Error::LoanNotLiquidatable))
+    );
+
+    oracle.set_price(&token_collateral.address, &(4000 * PRICE_SCALE));
+
+    assert_eq!(
+        client.try_liquidate(&liquidator, &loan_id, &0),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_liquidate(&liquidator, &loan_id, &400001),
+        Err(Ok(Error::AmountExceedsDebt))
+    );
+    assert_eq!(
+        client.try_liquidate(&liquidator, &(loan_id + 1), &1000),
+        Err(Ok(Error::LoanNotFound))
+    );
+}
+
+#[test]
+fn pool_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, _) = initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("pool"),
+                    symbol_short!("supply"),
+                    lender.clone()
+                )
+                    .into_val(&env),
+                PoolEvent {
+                    amount: 400000,
+                    shares: 400000,
+                    total_cash: 400000,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    client.withdraw_loan_tokens(&lender, &100000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("pool"),
+                    symbol_short!("withdraw"),
+                    lender.clone()
+                )
+                    .into_val(&env),
+                PoolEvent {
+                    amount: 100000,
+                    shares: 100000,
+                    total_cash: 300000,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+}
+
+#[test]
+fn loan_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&borrower, &6000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("opened"),
+                    borrower.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 100,
+                    principal: 400000,
+                    interest: 0,
+                    penalty: 0,
+                    total_cash: 0,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    client.repay_loan(&loan_id, &106000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("repaid"),
+                    borrower.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 0,
+                    principal: 100000,
+                    interest: 6000,
+                    penalty: 0,
+                    total_cash: 106000,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    client.repay_loan(&loan_id, &300000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("repaid"),
+                    borrower.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 100,
+                    principal: 300000,
+                    interest: 0,
+                    penalty: 0,
+                    total_cash: 406000,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+}
+
+#[test]
+fn admin_parameter_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let new_admin = Address::random(&env);
+    let oracle = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    client.propose_admin(&new_admin);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("proposed")).into_val(&env),
+                new_admin.into_val(&env),
+            )
+        ]
+    );
+
+    client.accept_admin();
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("admin")).into_val(&env),
+                new_admin.into_val(&env),
+            )
+        ]
+    );
+
+    let admin = new_admin;
+
+    client.set_oracle(&admin, &oracle, &3600, &7);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("oracle")).into_val(&env),
+                OracleConfig {
+                    address: oracle,
+                    max_age: 3600,
+                    decimals: 7,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("liq_param")).into_val(&env),
+                (850000_i128, 50000_i128).into_val(&env),
+            )
+        ]
+    );
+
+    client.set_rate_model(&admin, &10000, &0, &0, &1000000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), Symbol::new(&env, "rate_model")).into_val(&env),
+                InterestRateModel::flat(10000).into_val(&env),
+            )
+        ]
+    );
+}
+
+#[test]
+fn rates_follow_utilization() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &1000000);
+    token_collateral_admin.mint(&borrower, &200);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(
+        client.try_set_rate_model(&admin, &20000, &40000, &600000, &0),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_set_rate_model(&admin, &20000, &-1, &600000, &800000),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_set_rate_model(&admin, &20000, &40000, &600000, &800000),
+        Err(Ok(Error::RequiresTimelock))
+    );
+
+    // Cut to zero meanwhile, so the empty pool's index stays put.
+    client.set_interest_rate(&admin, &0);
+
+    let eta = env.ledger().timestamp() + TIMELOCK_DELAY;
+
+    let change = client.queue_change(
+        &admin,
+        &ParamChange::RateModel(InterestRateModel {
+            base_rate: 20000,
+            slope_1: 40000,
+            slope_2: 600000,
+            optimal_utilization: 800000,
+        }),
+        &eta,
+    );
+
+    env.ledger().with_mut(|li| li.timestamp = eta);
+
+    client.execute_change(&change);
+
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
+
+    client.supply_loan_tokens(&lender, &1000000);
+
+    // Nothing lent out: borrowers pay the base rate and lenders earn nothing.
+    assert_eq!(client.get_current_rates(), (20000, 0));
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    // 40% utilization, half way up the first slope.
+    assert_eq!(client.get_current_rates(), (40000, 16000));
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    assert_eq!(client.get_debt(&loan_id), 416000);
+
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
+
+    client.request_loan(&borrower, &100);
+
+    // 816000 borrowed out of 1016000: past the kink, on the steep slope.
+    assert_eq!(client.get_current_rates(), (69447, 55776));
+}
+
+#[test]
+fn admin_transfer_takes_two_steps() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let new_admin = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    assert_eq!(client.try_accept_admin(), Err(Ok(Error::NoPendingAdmin)));
+
+    client.propose_admin(&new_admin);
+
+    assert_eq!(
+        env.auths(),
+        std::vec![(
+            admin.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    contract_id.clone(),
+                    Symbol::new(&env, "propose_admin"),
+                    (new_admin.clone(),).into_val(&env),
+                )),
+                sub_invocations: std::vec![],
+            }
+        )]
+    );
+
+    // Nothing moves until the new admin accepts.
+    assert_eq!(client.read_administrator(), admin);
+    assert_eq!(client.get_pending_admin(), Some(new_admin.clone()));
+
+    client.accept_admin();
+
+    assert_eq!(
+        env.auths(),
+        std::vec![(
+            new_admin.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    contract_id.clone(),
+                    Symbol::new(&env, "accept_admin"),
+                    ().into_val(&env),
+                )),
+                sub_invocations: std::vec![],
+            }
+        )]
+    );
+
+    assert_eq!(client.read_administrator(), new_admin);
+    assert_eq!(client.get_pending_admin(), None);
+
+    assert!(client.has_role(&Role::Admin, &new_admin));
+    assert!(!client.has_role(&Role::Admin, &admin));
+    assert_eq!(
+        client.try_set_grace_period(&admin, &3600),
+        Err(Ok(Error::Unauthorized))
+    );
+}
+
+#[test]
+fn initialize_with_interest_rate_and_installments() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &30000,
+        &4,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    let loan = client.get_loan(&loan_id);
+    assert_eq!(loan.due_dates.len(), 4);
+    assert_eq!(loan.installment_amount, 100000);
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    assert_eq!(client.get_debt(&loan_id), 412000);
+}
+
+#[test]
+fn add_and_remove_collateral() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &150);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    // The loan is already at the collateral rate, nothing can come out.
+    assert_eq!(
+        client.try_remove_collateral(&loan_id, &1),
+        Err(Ok(Error::InsufficientCollateral))
+    );
+
+    assert_eq!(client.add_collateral(&loan_id, &50), 150);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("coll_add"),
+                    borrower.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 50,
+                    principal: 0,
+                    interest: 0,
+                    penalty: 0,
+                    total_cash: 0,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_loan(&loan_id).amount_locked, 150);
+    assert_eq!(token_collateral.balance(&borrower), 0);
+    assert_eq!(token_collateral.balance(&contract_id), 150);
+
+    assert_eq!(
+        client.try_remove_collateral(&loan_id, &151),
+        Err(Ok(Error::InsufficientCollateral))
+    );
+    assert_eq!(
+        client.try_remove_collateral(&loan_id, &60),
+        Err(Ok(Error::InsufficientCollateral))
+    );
+    assert_eq!(
+        client.try_add_collateral(&loan_id, &0),
+        Err(Ok(Error::InvalidAmount))
+    );
+
+    assert_eq!(client.remove_collateral(&loan_id, &50), 100);
+
+    assert_eq!(client.get_loan(&loan_id).amount_locked, 100);
+    assert_eq!(token_collateral.balance(&borrower), 50);
+    assert_eq!(token_collateral.balance(&contract_id), 100);
+}
+
+#[test]
+fn borrow_in_steps_against_collateral() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.deposit_collateral(&borrower, &100);
+
+    assert_eq!(token_collateral.balance(&contract_id), 100);
+    assert_eq!(client.get_debt(&loan_id), 0);
+    assert_eq!(client.max_borrowable(&loan_id), 400000);
+    assert_eq!(
+        client.get_next_installment(&loan_id),
+        Installment {
+            due_date: 0,
+            amount: 0,
+        }
+    );
+
+    assert_eq!(client.borrow(&loan_id, &150000), 150000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("borrow"),
+                    borrower.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 0,
+                    principal: 150000,
+                    interest: 0,
+                    penalty: 0,
+                    total_cash: 250000,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(token_to_lend.balance(&borrower), 150000);
+    assert_eq!(client.max_borrowable(&loan_id), 250000);
+
+    assert_eq!(
+        client.try_borrow(&loan_id, &250001),
+        Err(Ok(Error::InsufficientCollateral))
+    );
+    assert_eq!(
+        client.try_borrow(&loan_id, &0),
+        Err(Ok(Error::InvalidAmount))
+    );
+
+    env.ledger()
+        .with_mut(|li| li.timestamp += INSTALLMENT_PERIOD);
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
+
+    // A second draw reschedules all the principal from now on.
+    client.borrow(&loan_id, &50000);
+
+    let loan = client.get_loan(&loan_id);
+
+    assert_eq!(loan.principal, 200000);
+    assert_eq!(loan.installment_amount, 66666);
+    assert_eq!(loan.due_dates.first().unwrap(), 2 * INSTALLMENT_PERIOD);
+    assert_eq!(token_to_lend.balance(&borrower), 200000);
+    assert_eq!(
+        client.max_borrowable(&loan_id),
+        400000 - client.get_debt(&loan_id)
+    );
+}
+
+#[test]
+fn withdraw_all_collateral_of_unused_loan() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    let loan_id = client.deposit_collateral(&borrower, &100);
+
+    assert_eq!(client.remove_collateral(&loan_id, &100), 0);
+
+    assert_eq!(token_collateral.balance(&borrower), 100);
+    assert_eq!(client.try_get_loan(&loan_id), Err(Ok(Error::LoanNotFound)));
+    assert_eq!(client.get_loans_of(&borrower).len(), 0);
+}
+
+#[test]
+fn mark_default_after_grace_period() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(
+        client.try_set_grace_period(&admin, &(MAX_GRACE_PERIOD + 1)),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    let grace_period = 7 * 24 * 60 * 60;
+
+    client.set_grace_period(&admin, &grace_period);
+
+    assert_eq!(client.get_grace_period(), grace_period);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    env.ledger().with_mut(|li| li.timestamp += 1000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    let loan = client.get_loan(&loan_id);
+
+    assert_eq!(loan.opened_at, 1000);
+    assert_eq!(loan.due_at, 1000 + 3 * INSTALLMENT_PERIOD);
+
+    env.ledger()
+        .with_mut(|li| li.timestamp = loan.due_at + grace_period);
+
+    assert_eq!(
+        client.try_mark_default(&loan_id),
+        Err(Ok(Error::LoanNotDefaulted))
+    );
+
+    env.ledger().with_mut(|li| li.timestamp += 1);
+
+    let outstanding = client.get_debt(&loan_id);
+
+    assert!(outstanding > 400000);
+
+    assert_eq!(
+        client.try_mark_default(&loan_id),
+        Err(Ok(Error::TreasuryNotSet))
+    );
+
+    let treasury = Address::random(&env);
+
+    client.set_treasury(&treasury);
+
+    assert_eq!(client.mark_default(&loan_id), outstanding);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("default"),
+                    borrower.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 100,
+                    principal: 400000,
+                    interest: outstanding - 400000,
+                    penalty: 0,
+                    total_cash: 0,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    // The collateral goes to the treasury and the lenders lose what was lent.
+    assert_eq!(token_collateral.balance(&treasury), 100);
+    assert_eq!(token_collateral.balance(&contract_id), 0);
+    assert_eq!(client.get_shares_value(&client.get_shares(&lender)), 0);
+    assert_eq!(client.try_get_loan(&loan_id), Err(Ok(Error::LoanNotFound)));
+    assert_eq!(
+        client.try_mark_default(&loan_id),
+        Err(Ok(Error::LoanNotFound))
+    );
+
+    // Once sold, the proceeds go back to the lenders.
+    token_lend_admin.mint(&treasury, &300000);
+
+    assert_eq!(client.recover(&treasury, &300000), 300000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("pool"),
+                    symbol_short!("recover"),
+                    treasury.clone()
+                )
+                    .into_val(&env),
+                PoolEvent {
+                    amount: 300000,
+                    shares: 0,
+                    total_cash: 300000,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_shares_value(&client.get_shares(&lender)), 300000);
+
+    client.withdraw_loan_tokens(&lender, &400000);
+
+    assert_eq!(token_to_lend.balance(&lender), 300000);
+}
+
+#[test]
+fn late_payment_penalty() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let treasury = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    // No interest, so only late fees add to the debt.
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &0,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(
+        client.try_set_penalty_params(&admin, &1000, &1000001),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    client.set_penalty_params(&admin, &1000, &500000);
+    client.set_treasury(&treasury);
+
+    assert_eq!(client.get_penalty_params(), (1000, 500000));
+    assert_eq!(client.get_treasury(), Some(treasury.clone()));
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    env.ledger()
+        .with_mut(|li| li.timestamp = INSTALLMENT_PERIOD);
+
+    assert_eq!(client.get_debt(&loan_id), 400000);
+
+    // Ten days late on the first installment of 133333.
+    env.ledger()
+        .with_mut(|li| li.timestamp += 10 * SECONDS_PER_DAY);
+
+    assert_eq!(client.get_debt(&loan_id), 401334);
+    assert_eq!(client.get_next_installment(&loan_id).amount, 1334 + 133333);
+    assert_eq!(client.get_loan(&loan_id).penalty, 1334);
+
+    client.repay_loan(&loan_id, &(1334 + 133333));
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("loan"),
+                    symbol_short!("repaid"),
+                    borrower.clone()
+                )
+                    .into_val(&env),
+                LoanEvent {
+                    loan_id,
+                    collateral: 0,
+                    principal: 133333,
+                    interest: 0,
+                    penalty: 1334,
+                    total_cash: 133333 + 667,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(token_to_lend.balance(&treasury), 667);
+    assert_eq!(client.get_total_supply(), 133333 + 667);
+    assert_eq!(client.get_loan(&loan_id).penalty, 0);
+
+    // Nothing is overdue again until the second due date.
+    env.ledger()
+        .with_mut(|li| li.timestamp += 10 * SECONDS_PER_DAY);
+
+    assert_eq!(client.get_debt(&loan_id), 266667);
+}
+
+#[test]
+fn reserve_factor_and_claim_reserves() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let treasury = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&borrower, &6000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(
+        client.try_set_reserve_factor(&admin, &1000001),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    client.set_reserve_factor(&admin, &100000);
+
+    assert_eq!(client.get_reserve_factor(), 100000);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    // Everything is lent out and a tenth of the interest is kept.
+    assert_eq!(client.get_current_rates(), (15000, 13500));
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    assert_eq!(
+        client.try_claim_reserves(&admin, &100),
+        Err(Ok(Error::TreasuryNotSet))
+    );
+
+    client.set_treasury(&treasury);
+
+    client.repay_loan(&loan_id, &406000);
+
+    // Lenders get the interest minus the reserves.
+    assert_eq!(client.get_reserves(), 600);
+    assert_eq!(client.get_shares_value(&client.get_shares(&lender)), 405400);
+
+    assert_eq!(
+        client.try_claim_reserves(&admin, &601),
+        Err(Ok(Error::InsufficientReserves))
+    );
+
+    assert_eq!(client.claim_reserves(&admin, &100), 500);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("pool"),
+                    symbol_short!("claim"),
+                    treasury.clone()
+                )
+                    .into_val(&env),
+                (100_i128, 500_i128).into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(token_to_lend.balance(&treasury), 100);
+    assert_eq!(client.get_total_supply(), 405900);
+    assert_eq!(client.get_shares_value(&client.get_shares(&lender)), 405400);
+}
+
+#[test]
+fn reserves_accrue_with_interest() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let treasury = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &500000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_reserve_factor(&admin, &100000);
+    client.set_treasury(&treasury);
+
+    client.supply_loan_tokens(&lender, &500000);
+
+    client.request_loan(&borrower, &100);
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    // Claiming accrues first, so the reserves come from interest nobody has
+    // paid yet. They are backed by the cash the pool still holds.
+    client.claim_reserves(&admin, &600);
+
+    let events = env.events().all();
+
+    assert_eq!(
+        events.slice(events.len() - 3..events.len() - 2),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("pool"), symbol_short!("reserves")).into_val(&env),
+                (600_i128, 600_i128).into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_reserves(), 0);
+    assert_eq!(token_to_lend.balance(&treasury), 600);
+    assert_eq!(client.get_total_supply(), 99400);
+}
+
+#[test]
+fn pool_state_tracks_cash_borrows_and_reserves() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &500000);
+    token_lend_admin.mint(&borrower, &6000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_reserve_factor(&admin, &100000);
+
+    client.supply_loan_tokens(&lender, &500000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    assert_eq!(
+        client.get_pool_state(),
+        PoolState {
+            total_cash: 100000,
+            total_borrows: 400000,
+            total_reserves: 0,
+            total_shares: 500000,
+        }
+    );
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    // Interest shows up in borrows and reserves before anything is paid.
+    assert_eq!(
+        client.get_pool_state(),
+        PoolState {
+            total_cash: 100000,
+            total_borrows: 406000,
+            total_reserves: 600,
+            total_shares: 500000,
+        }
+    );
+
+    client.repay_loan(&loan_id, &406000);
+
+    let state = client.get_pool_state();
+
+    assert_eq!(
+        state,
+        PoolState {
+            total_cash: 506000,
+            total_borrows: 0,
+            total_reserves: 600,
+            total_shares: 500000,
+        }
+    );
+    assert_eq!(state.total_cash, token_to_lend.balance(&contract_id));
+}
+
+#[test]
+fn collateral_with_fewer_decimals_than_loan_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let liquidator = Address::random(&env);
+
+    let one_loan_token: i128 = 1_000_000_000_000_000_000;
+    let one_collateral_token: i128 = 10_000_000;
+
+    let token_to_lend = create_mock_token(&env, 18);
+    let token_collateral = create_mock_token(&env, 7);
+
+    token_to_lend.mint(&lender, &(2 * one_loan_token));
+    token_to_lend.mint(&liquidator, &one_loan_token);
+    token_collateral.mint(&borrower, &one_collateral_token);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    // One collateral token is worth two loan tokens.
+    let oracle = create_oracle(&env);
+    oracle.set_price(&token_collateral.address, &(2 * PRICE_SCALE));
+    client.set_oracle(&admin, &oracle.address, &3600, &7);
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+
+    assert_eq!(
+        client.get_loan_amount(&one_collateral_token),
+        one_loan_token * 16 / 10
+    );
+
+    client.supply_loan_tokens(&lender, &(2 * one_loan_token));
+
+    let loan_id = client.request_loan(&borrower, &one_collateral_token);
+
+    assert_eq!(token_to_lend.balance(&borrower), one_loan_token * 16 / 10);
+
+    oracle.set_price(&token_collateral.address, &(PRICE_SCALE * 15 / 10));
+
+    // Half a loan token plus the 5% bonus buys 0.35 collateral tokens at 1.5.
+    assert_eq!(
+        client.liquidate(&liquidator, &loan_id, &(one_loan_token / 2)),
+        one_collateral_token * 35 / 100
+    );
+    assert_eq!(
+        token_collateral.balance(&liquidator),
+        one_collateral_token * 35 / 100
+    );
+}
+
+#[test]
+fn collateral_with_more_decimals_than_loan_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let one_loan_token: i128 = 1_000_000;
+    let one_collateral_token: i128 = 1_000_000_000_000_000_000;
+
+    let token_to_lend = create_mock_token(&env, 6);
+    let token_collateral = create_mock_token(&env, 18);
+
+    token_to_lend.mint(&lender, &(5000 * one_loan_token));
+    token_collateral.mint(&borrower, &one_collateral_token);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = create_oracle(&env);
+    oracle.set_price(&token_collateral.address, &(2000 * PRICE_SCALE));
+    client.set_oracle(&admin, &oracle.address, &3600, &7);
+
+    client.supply_loan_tokens(&lender, &(5000 * one_loan_token));
+
+    let loan_id = client.request_loan(&borrower, &one_collateral_token);
+
+    assert_eq!(client.get_debt(&loan_id), 1600 * one_loan_token);
+    assert_eq!(token_to_lend.balance(&borrower), 1600 * one_loan_token);
+
+    // Half the collateral can come out once half the debt is repaid.
+    client.repay_loan(&loan_id, &(800 * one_loan_token));
+
+    assert_eq!(
+        client.remove_collateral(&loan_id, &(one_collateral_token / 2)),
+        one_collateral_token / 2
+    );
+}
+
+const EXTREMES: [i128; 13] = [
+    i128::MIN,
+    i128::MIN + 1,
+    -1_000_000_000_000_000_000_000,
+    -1_000_000,
+    -7,
+    -1,
+    0,
+    1,
+    7,
+    1_000_000,
+    1_000_000_000_000_000_000_000,
+    i128::MAX - 1,
+    i128::MAX,
+];
+
+#[test]
+fn mul_div_matches_exact_division() {
+    for a in EXTREMES {
+        for b in EXTREMES {
+            for c in EXTREMES {
+                let floor = mul_div_floor(a, b, c);
+                let ceil = mul_div_ceil(a, b, c);
+
+                if c == 0 {
+                    assert_eq!(floor, Err(Error::DivisionByZero));
+                    assert_eq!(ceil, Err(Error::DivisionByZero));
+                    continue;
+                }
+
+                // Order of the factors never matters.
+                assert_eq!(floor, mul_div_floor(b, a, c));
+                assert_eq!(ceil, mul_div_ceil(b, a, c));
+
+                let Some(product) = a.checked_mul(b) else {
+                    continue;
+                };
+
+                // Euclidean division rounds down for a positive divisor and
+                // up for a negative one.
+                match product.checked_div_euclid(c) {
+                    Some(quotient) if c > 0 => assert_eq!(floor, Ok(quotient)),
+                    Some(quotient) => assert_eq!(ceil, Ok(quotient)),
+                    None => assert_eq!(floor, Err(Error::MathOverflow)),
+                }
+
+                if let (Ok(floor), Ok(ceil)) = (floor, ceil) {
+                    let exact = product % c == 0;
+                    assert_eq!(ceil - floor, if exact { 0 } else { 1 });
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn mul_div_beyond_i128_products() {
+    for a in EXTREMES {
+        for b in EXTREMES {
+            if b == 0 {
+                continue;
+            }
+
+            // The product overflows an i128 but the quotient does not.
+            assert_eq!(mul_div_floor(a, b, b), Ok(a));
+            assert_eq!(mul_div_ceil(a, b, b), Ok(a));
+        }
+    }
+
+    assert_eq!(mul_div_floor(i128::MAX, 2, 4), Ok(i128::MAX / 2));
+    assert_eq!(mul_div_ceil(i128::MAX, 2, 4), Ok(i128::MAX / 2 + 1));
+    assert_eq!(mul_div_floor(i128::MIN, 3, 4), Ok(i128::MIN / 4 * 3));
+    assert_eq!(mul_div_floor(-i128::MAX, 2, 4), Ok(-i128::MAX / 2 - 1));
+    assert_eq!(mul_div_ceil(-i128::MAX, 2, 4), Ok(-i128::MAX / 2));
+
+    assert_eq!(mul_div_floor(i128::MAX, 2, 1), Err(Error::MathOverflow));
+    assert_eq!(
+        mul_div_ceil(i128::MAX, i128::MAX, 2),
+        Err(Error::MathOverflow)
+    );
+    assert_eq!(mul_div_floor(i128::MIN, -1, 1), Err(Error::MathOverflow));
+    assert_eq!(mul_div_floor(i128::MIN, 1, 1), Ok(i128::MIN));
+    assert_eq!(mul_div_ceil(i128::MIN, 3, 3), Ok(i128::MIN));
+
+    assert_eq!(pow10(38), Ok(10_i128.pow(38)));
+    assert_eq!(pow10(39), Err(Error::MathOverflow));
+    assert_eq!(rescale(i128::MAX, 6, 7), Err(Error::MathOverflow));
+    assert_eq!(rescale(-15, 1, 0), Ok(-2));
+}
+
+/// Xorshift with a fixed seed, so failures reproduce.
+struct Inputs(u64);
+
+impl Inputs {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A value of random sign with a random number of significant bits, so
+    /// small and huge magnitudes come up equally often.
+    fn next_i128(&mut self) -> i128 {
+        let bits = self.next_u64() % 128;
+        let magnitude =
+            (((self.next_u64() as u128) << 64) | self.next_u64() as u128) >> (127 - bits) >> 1;
+
+        if self.next_u64() & 1 == 0 {
+            magnitude as i128
+        } else {
+            -(magnitude as i128)
+        }
+    }
+}
+
+#[test]
+fn mul_div_on_generated_inputs() {
+    let mut inputs = Inputs(0x9e37_79b9_7f4a_7c15);
+
+    for _ in 0..20_000 {
+        let a = inputs.next_i128();
+        let b = inputs.next_i128();
+        let c = inputs.next_i128();
+
+        if c == 0 {
+            assert_eq!(mul_div_floor(a, b, c), Err(Error::DivisionByZero));
+            continue;
+        }
+
+        let floor = mul_div_floor(a, b, c);
+        let ceil = mul_div_ceil(a, b, c);
+
+        assert_eq!(floor, mul_div_floor(b, a, c));
+        assert_eq!(ceil, mul_div_ceil(b, a, c));
+
+        if let Some(product) = a.checked_mul(b) {
+            let quotient = product.div_euclid(c);
+
+            if c > 0 {
+                assert_eq!(floor, Ok(quotient));
+            } else {
+                assert_eq!(ceil, Ok(quotient));
+            }
+        }
+
+        if let (Ok(floor), Ok(ceil)) = (floor, ceil) {
+            assert!(ceil - floor == 0 || ceil - floor == 1);
+        }
+
+        // When `c` divides `b` the result is exact even if `a * b` does not
+        // fit in an i128.
+        let m = inputs.next_i128() >> 64;
+
+        if let Some(b) = c.checked_mul(m) {
+            let expected = a.checked_mul(m).ok_or(Error::MathOverflow);
+
+            assert_eq!(mul_div_floor(a, b, c), expected);
+            assert_eq!(mul_div_ceil(a, b, c), expected);
+        }
+
+        assert_eq!(add(a, b), a.checked_add(b).ok_or(Error::MathOverflow));
+        assert_eq!(sub(a, b), a.checked_sub(b).ok_or(Error::MathOverflow));
+    }
+}
+
+#[test]
+fn huge_pools_fail_with_typed_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+
+    let token_to_lend = create_mock_token(&env, 18);
+    let token_collateral = create_mock_token(&env, 7);
+
+    token_to_lend.mint(&lender, &i128::MAX);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    // Share math on 18 decimal amounts would overflow a plain i128 product.
+    let deposit = 1_000_000_000_000_000_000_000_000_i128;
+
+    client.supply_loan_tokens(&lender, &deposit);
+    client.supply_loan_tokens(&lender, &deposit);
+
+    assert_eq!(
+        client.get_shares_value(&client.get_shares(&lender)),
+        2 * deposit
+    );
+
+    let oracle = create_oracle(&env);
+    oracle.set_price(&token_collateral.address, &i128::MAX);
+    client.set_oracle(&admin, &oracle.address, &3600, &0);
+
+    assert_eq!(
+        client.try_get_loan_amount(&i128::MAX),
+        Err(Ok(Error::MathOverflow))
+    );
+}
+
+#[test]
+fn negative_amounts_are_rejected() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let liquidator = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_treasury(&admin);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.deposit_collateral(&borrower, &50);
+
+    assert_eq!(
+        client.try_supply_loan_tokens(&lender, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_withdraw_loan_tokens(&lender, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_request_loan(&borrower, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_deposit_collateral(&borrower, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_borrow(&loan_id, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_repay_loan(&loan_id, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_add_collateral(&loan_id, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_remove_collateral(&loan_id, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_liquidate(&liquidator, &loan_id, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_claim_reserves(&admin, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_get_loan_amount(&-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_get_shares_value(&-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+
+    // Nothing moved on any of the failed attempts.
+    assert_eq!(token_to_lend.balance(&lender), 0);
+    assert_eq!(token_to_lend.balance(&borrower), 0);
+    assert_eq!(token_collateral.balance(&borrower), 50);
+    assert_eq!(
+        client.get_pool_state(),
+        PoolState {
+            total_cash: 400000,
+            total_borrows: 0,
+            total_reserves: 0,
+            total_shares: 400000,
+        }
+    );
+}
+
+#[test]
+fn loan_size_limits() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    assert_eq!(client.get_loan_limits(), (0, i128::MAX));
+
+    assert_eq!(
+        client.try_set_loan_limits(&admin, &-1, &300000),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_set_loan_limits(&admin, &300000, &100000),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    client.set_loan_limits(&admin, &100000, &300000);
+
+    assert_eq!(client.get_loan_limits(), (100000, 300000));
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    // 100 collateral would borrow 400000, over the maximum.
+    assert_eq!(
+        client.try_request_loan(&borrower, &100),
+        Err(Ok(Error::LoanTooLarge))
+    );
+
+    let loan_id = client.deposit_collateral(&borrower, &100);
+
+    assert_eq!(
+        client.try_borrow(&loan_id, &99999),
+        Err(Ok(Error::LoanTooSmall))
+    );
+
+    client.borrow(&loan_id, &250000);
+
+    // The limit is on the whole loan, not on each draw.
+    assert_eq!(
+        client.try_borrow(&loan_id, &50001),
+        Err(Ok(Error::LoanTooLarge))
+    );
+
+    assert_eq!(client.borrow(&loan_id, &50000), 300000);
+    assert_eq!(token_to_lend.balance(&borrower), 300000);
+}
+
+fn setup_paused_pool<'a>(
+    env: &Env,
+) -> (
+    CollateralizedLoanContractClient<'a>,
+    MockOracleClient<'a>,
+    Address,
+    Address,
+    u64,
+) {
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(env);
+    let borrower = Address::random(env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &500000);
+    token_lend_admin.mint(&borrower, &100000);
+    token_collateral_admin.mint(&borrower, &200);
+
+    let admin = Address::random(env);
+
+    let client = CollateralizedLoanContractClient::new(env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(env, &client, &token_collateral.address);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let loan_id = client.deposit_collateral(&borrower, &100);
+    client.borrow(&loan_id, &100000);
+
+    (client, oracle, lender, borrower, loan_id)
+}
+
+#[test]
+fn pause_supply() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _, lender, _, _) = setup_paused_pool(&env);
+    let admin = client.read_administrator();
+
+    assert_eq!(client.get_pause_state(), PauseState::default());
+
+    client.set_paused(&admin, &Action::Supply, &true);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                client.address.clone(),
+                (symbol_short!("admin"), symbol_short!("pause")).into_val(&env),
+                PauseState {
+                    supply: true,
+                    borrow: false,
+                    repay: false,
+                    withdraw: false,
+                    liquidate: false,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(
+        client.try_supply_loan_tokens(&lender, &1000),
+        Err(Ok(Error::Paused))
+    );
+
+    // Lenders can still leave.
+    client.withdraw_loan_tokens(&lender, &1000);
+
+    client.set_paused(&admin, &Action::Supply, &false);
+
+    assert_eq!(client.get_pause_state(), PauseState::default());
+
+    client.supply_loan_tokens(&lender, &1000);
+}
+
+#[test]
+fn pause_borrow() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _, _, borrower, loan_id) = setup_paused_pool(&env);
+    let admin = client.read_administrator();
+
+    client.set_paused(&admin, &Action::Borrow, &true);
+
+    assert!(client.get_pause_state().borrow);
+
+    assert_eq!(
+        client.try_request_loan(&borrower, &50),
+        Err(Ok(Error::Paused))
+    );
+    assert_eq!(client.try_borrow(&loan_id, &1000), Err(Ok(Error::Paused)));
+    assert_eq!(
+        client.try_remove_collateral(&loan_id, &10),
+        Err(Ok(Error::Paused))
+    );
+
+    // Adding collateral and repaying only make the loan safer.
+    client.add_collateral(&loan_id, &50);
+    client.repay_loan(&loan_id, &1000);
+
+    client.set_paused(&admin, &Action::Borrow, &false);
+
+    client.borrow(&loan_id, &1000);
+}
+
+#[test]
+fn pause_repay() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _, _, _, loan_id) = setup_paused_pool(&env);
+    let admin = client.read_administrator();
+
+    client.set_paused(&admin, &Action::Repay, &true);
+
+    assert!(client.get_pause_state().repay);
+
+    assert_eq!(
+        client.try_repay_loan(&loan_id, &1000),
+        Err(Ok(Error::Paused))
+    );
+
+    client.set_paused(&admin, &Action::Repay, &false);
+
+    client.repay_loan(&loan_id, &1000);
+}
+
+#[test]
+fn pause_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _, lender, _, _) = setup_paused_pool(&env);
+    let admin = client.read_administrator();
+
+    client.set_paused(&admin, &Action::Withdraw, &true);
+
+    assert!(client.get_pause_state().withdraw);
+
+    assert_eq!(
+        client.try_withdraw_loan_tokens(&lender, &1000),
+        Err(Ok(Error::Paused))
+    );
+
+    client.set_paused(&admin, &Action::Withdraw, &false);
+
+    client.withdraw_loan_tokens(&lender, &1000);
+}
+
+#[test]
+fn pause_liquidate() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, oracle, lender, _, loan_id) = setup_paused_pool(&env);
+    let admin = client.read_administrator();
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+
+    // 100 collateral at 1000 is worth 100000, at the debt.
+    oracle.set_price(&client.get_collateral_token(), &(1000 * PRICE_SCALE));
+
+    client.set_paused(&admin, &Action::Liquidate, &true);
+
+    assert!(client.get_pause_state().liquidate);
+
+    assert_eq!(
+        client.try_liquidate(&lender, &loan_id, &10000),
+        Err(Ok(Error::Paused))
+    );
+    assert_eq!(client.try_mark_default(&loan_id), Err(Ok(Error::Paused)));
+
+    client.set_paused(&admin, &Action::Liquidate, &false);
+
+    client.liquidate(&lender, &loan_id, &10000);
+}
+
+#[test]
+fn emergency_pause_keeps_repay_open() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _, lender, borrower, loan_id) = setup_paused_pool(&env);
+    let admin = client.read_administrator();
+
+    client.pause(&admin);
+
+    assert_eq!(
+        client.get_pause_state(),
+        PauseState {
+            supply: true,
+            borrow: true,
+            repay: false,
+            withdraw: true,
+            liquidate: true,
+        }
+    );
+
+    assert_eq!(
+        client.try_supply_loan_tokens(&lender, &1000),
+        Err(Ok(Error::Paused))
+    );
+    assert_eq!(
+        client.try_withdraw_loan_tokens(&lender, &1000),
+        Err(Ok(Error::Paused))
+    );
+    assert_eq!(
+        client.try_request_loan(&borrower, &50),
+        Err(Ok(Error::Paused))
+    );
+
+    client.add_collateral(&loan_id, &50);
+    client.repay_loan(&loan_id, &1000);
+
+    client.unpause(&admin);
+
+    assert_eq!(client.get_pause_state(), PauseState::default());
+
+    client.supply_loan_tokens(&lender, &1000);
+}
+
+#[test]
+fn migrate_first_release_storage() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let borrower = Address::random(&env);
+    let lender = Address::random(&env);
+    let admin = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&contract_id, &300000);
+    token_collateral_admin.mint(&contract_id, &100);
+
+    // What the first release left behind after one loan of 100000 against
+    // 100 collateral out of a 400000 supply, at 1000 loan tokens per
+    // collateral unit.
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().instance();
+
+        storage.set(&DataKey::Admin, &admin);
+        storage.set(&DataKey::LoanToken, &token_to_lend.address);
+        storage.set(&DataKey::CollateralToken, &token_collateral.address);
+        storage.set(&DataKey::InterestRate, &15000_i128);
+        storage.set(&DataKey::CollateralRate, &1000_i128);
+        storage.set(&DataKey::Installments, &3_i32);
+        storage.set(&DataKey::TotalSupply, &300000_i128);
+        storage.set(
+            &DataKey::Borrowers,
+            &vec![
+                &env,
+                LegacyBorrower {
+                    address: borrower.clone(),
+                    amount_locked: 100,
+                    debt: 100000,
+                },
+            ],
+        );
+        storage.set(&DataKey::BorrowersAddresses, &vec![&env, borrower.clone()]);
+    });
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    assert_eq!(client.get_version(), 0);
+
+    // The old rate is in other units, so a new one has to be given.
+    assert_eq!(
+        client.try_migrate(&0),
+        Err(Ok(Error::InvalidCollateralRate))
+    );
+    assert_eq!(
+        client.try_migrate(&1000000),
+        Err(Ok(Error::InvalidCollateralRate))
+    );
+
+    // Nobody knows which lender owns the cash and the debt.
+    assert_eq!(
+        client.try_migrate(&800000),
+        Err(Ok(Error::LegacyPoolNotEmpty))
+    );
+    assert_eq!(client.get_version(), 0);
+
+    // Once the first release's lenders are paid out and its loan repaid,
+    // only the collateral is left.
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().instance();
+
+        storage.set(&DataKey::TotalSupply, &0_i128);
+        storage.set(
+            &DataKey::Borrowers,
+            &vec![
+                &env,
+                LegacyBorrower {
+                    address: borrower.clone(),
+                    amount_locked: 100,
+                    debt: 0,
+                },
+            ],
+        );
+    });
+
+    token_to_lend.transfer(&contract_id, &admin, &300000);
+
+    assert_eq!(client.migrate(&800000), STORAGE_VERSION);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("migrate")).into_val(&env),
+                STORAGE_VERSION.into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_version(), STORAGE_VERSION);
+    assert_eq!(client.try_migrate(&800000), Err(Ok(Error::AlreadyMigrated)));
+
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().instance();
+
+        assert!(!storage.has(&DataKey::InterestRate));
+        assert!(!storage.has(&DataKey::TotalSupply));
+        assert!(!storage.has(&DataKey::Borrowers));
+        assert!(!storage.has(&DataKey::BorrowersAddresses));
+    });
+
+    assert_eq!(client.get_collateral_rate(), 800000);
+    assert_eq!(client.get_rate_model(), InterestRateModel::flat(15000));
+
+    // No shares were made up for anyone.
+    assert_eq!(
+        client.get_pool_state(),
+        PoolState {
+            total_cash: 0,
+            total_borrows: 0,
+            total_reserves: 0,
+            total_shares: 0,
+        }
+    );
+    assert_eq!(client.get_shares(&admin), 0);
+
+    let loans = client.get_loans_of(&borrower);
+
+    assert_eq!(loans.len(), 1);
+
+    let loan = loans.get(0).unwrap();
+
+    assert_eq!(loan.amount_locked, 100);
+    assert_eq!(loan.debt, 0);
+    assert_eq!(loan.due_dates.len(), 0);
+
+    // The migrated loan works like any other.
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    token_lend_admin.mint(&lender, &400000);
+    client.supply_loan_tokens(&lender, &400000);
+
+    assert_eq!(client.borrow(&loan.id, &100000), 100000);
+    assert_eq!(client.get_loan(&loan.id).due_dates.len(), 3);
+    assert_eq!(token_to_lend.balance(&borrower), 100000);
+
+    assert_eq!(client.repay_loan(&loan.id, &100000), 0);
+    assert_eq!(token_collateral.balance(&borrower), 100);
+    assert_eq!(client.get_total_supply(), 400000);
+}
+
+// Built from this crate and `fixtures/upgraded` by `fixtures/build.sh`, which
+// has to be rerun when the contract interface changes.
+mod deployed {
+    soroban_sdk::contractimport!(file = "fixtures/collateralized_loans.wasm");
+}
+
+mod upgraded {
+    soroban_sdk::contractimport!(file = "fixtures/upgraded.wasm");
+}
+
+#[test]
+fn upgrade_swaps_code_and_keeps_storage() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    // The test shares one budget across every call, and each wasm call pays
+    // for loading the contract.
+    env.budget().reset_unlimited();
+
+    let contract_id = env.register_contract_wasm(None, deployed::WASM);
+
+    let lender = Address::random(&env);
+    let admin = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, _) = initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    let new_wasm_hash = env.deployer().upload_contract_wasm(upgraded::WASM);
+
+    client.upgrade(&new_wasm_hash);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("upgrade")).into_val(&env),
+                new_wasm_hash.into_val(&env),
+            )
+        ]
+    );
+
+    let client = upgraded::Client::new(&env, &contract_id);
+
+    assert_eq!(client.code_version(), 2);
+    assert_eq!(client.read_administrator(), admin);
+    assert_eq!(client.get_total_supply(), 400000);
+    assert_eq!(token_to_lend.balance(&contract_id), 400000);
+}
+
+#[test]
+fn upgrade_and_migrate_need_an_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    let hash = BytesN::from_array(&env, &[0; 32]);
+
+    assert_eq!(client.try_upgrade(&hash), Err(Ok(Error::NotInitialized)));
+    assert_eq!(client.try_migrate(&800000), Err(Ok(Error::NotInitialized)));
+
+    let admin = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    // Fresh contracts start on the current layout.
+    assert_eq!(client.get_version(), STORAGE_VERSION);
+    assert_eq!(client.try_migrate(&800000), Err(Ok(Error::AlreadyMigrated)));
+}
+
+#[test]
+fn roles_split_admin_powers() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let risk_manager = Address::random(&env);
+    let guardian = Address::random(&env);
+    let treasurer = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    assert_eq!(
+        client.try_grant_role(&Role::Admin, &risk_manager),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    client.grant_role(&Role::RiskManager, &risk_manager);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("role"),
+                    symbol_short!("grant"),
+                    risk_manager.clone()
+                )
+                    .into_val(&env),
+                Role::RiskManager.into_val(&env),
+            )
+        ]
+    );
+
+    client.grant_role(&Role::PauseGuardian, &guardian);
+    client.grant_role(&Role::Treasurer, &treasurer);
+
+    assert!(client.has_role(&Role::RiskManager, &risk_manager));
+    assert!(!client.has_role(&Role::PauseGuardian, &risk_manager));
+    assert!(client.has_role(&Role::Treasurer, &admin));
+
+    client.set_rate_model(&risk_manager, &10000, &0, &0, &1000000);
+    client.set_reserve_factor(&risk_manager, &100000);
+
+    assert_eq!(
+        client.try_set_paused(&risk_manager, &Action::Borrow, &true),
+        Err(Ok(Error::Unauthorized))
+    );
+
+    client.set_paused(&guardian, &Action::Borrow, &true);
+
+    assert_eq!(
+        client.try_set_oracle(&guardian, &guardian, &3600, &7),
+        Err(Ok(Error::Unauthorized))
+    );
+    assert_eq!(
+        client.try_claim_reserves(&guardian, &1),
+        Err(Ok(Error::Unauthorized))
+    );
+
+    // The treasurer gets past the role check to the missing treasury.
+    assert_eq!(
+        client.try_claim_reserves(&treasurer, &1),
+        Err(Ok(Error::TreasuryNotSet))
+    );
+
+    client.revoke_role(&Role::RiskManager, &risk_manager);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("role"),
+                    symbol_short!("revoke"),
+                    risk_manager.clone()
+                )
+                    .into_val(&env),
+                Role::RiskManager.into_val(&env),
+            )
+        ]
+    );
+
+    assert!(!client.has_role(&Role::RiskManager, &risk_manager));
+    assert_eq!(
+        client.try_set_reserve_factor(&risk_manager, &0),
+        Err(Ok(Error::Unauthorized))
+    );
+}
+
+#[test]
+fn timelocked_rate_changes() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_collateral_admin.mint(&borrower, &100);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    let eta = env.ledger().timestamp() + TIMELOCK_DELAY;
+
+    assert_eq!(
+        client.try_queue_change(&admin, &ParamChange::CollateralRate(600000), &(eta - 1)),
+        Err(Ok(Error::DelayTooShort))
+    );
+    assert_eq!(
+        client.try_queue_change(&admin, &ParamChange::CollateralRate(0), &eta),
+        Err(Ok(Error::InvalidCollateralRate))
+    );
+
+    let rate_change = client.queue_change(&admin, &ParamChange::CollateralRate(600000), &eta);
+
+    let pending = PendingChange {
+        id: rate_change,
+        change: ParamChange::CollateralRate(600000),
+        eta,
+    };
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("timelock"),
+                    symbol_short!("queue"),
+                    rate_change
+                )
+                    .into_val(&env),
+                pending.clone().into_val(&env),
+            )
+        ]
+    );
+
+    let interest_change =
+        client.queue_change(&admin, &ParamChange::InterestRate(30000), &(eta + 60));
+
+    assert_eq!(client.get_pending_changes().len(), 2);
+    assert_eq!(client.get_pending_change(&rate_change), pending);
+
+    assert_eq!(
+        client.try_execute_change(&rate_change),
+        Err(Ok(Error::ChangeNotReady))
+    );
+
+    // Nothing changes while the change waits.
+    assert_eq!(client.get_loan_amount(&100), 400000);
+
+    env.ledger().with_mut(|li| li.timestamp = eta);
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
+
+    client.execute_change(&rate_change);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("timelock"),
+                    symbol_short!("execute"),
+                    rate_change
+                )
+                    .into_val(&env),
+                pending.into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_loan_amount(&100), 300000);
+    assert_eq!(
+        client.try_execute_change(&rate_change),
+        Err(Ok(Error::ChangeNotFound))
+    );
+
+    client.cancel_change(&admin, &interest_change);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (
+                    symbol_short!("timelock"),
+                    symbol_short!("cancel"),
+                    interest_change
+                )
+                    .into_val(&env),
+                PendingChange {
+                    id: interest_change,
+                    change: ParamChange::InterestRate(30000),
+                    eta: eta + 60,
+                }
+                .into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_pending_changes().len(), 0);
+    assert_eq!(
+        client.try_cancel_change(&admin, &interest_change),
+        Err(Ok(Error::ChangeNotFound))
+    );
+
+    let interest_change = client.queue_change(
+        &admin,
+        &ParamChange::InterestRate(30000),
+        &(eta + TIMELOCK_DELAY),
+    );
+
+    env.ledger()
+        .with_mut(|li| li.timestamp = eta + TIMELOCK_DELAY);
+
+    client.execute_change(&interest_change);
+
+    assert_eq!(client.get_rate_model(), InterestRateModel::flat(30000));
+}
+
+#[test]
+fn timelocked_token_changes_need_an_empty_pool() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    let new_loan_token = create_mock_token(&env, 6);
+    let new_collateral_token = create_mock_token(&env, 18);
+
+    let eta = env.ledger().timestamp() + TIMELOCK_DELAY;
+
+    let loan_token_change = client.queue_change(
+        &admin,
+        &ParamChange::LoanToken(new_loan_token.address.clone()),
+        &eta,
+    );
+    let collateral_token_change = client.queue_change(
+        &admin,
+        &ParamChange::CollateralToken(new_collateral_token.address.clone()),
+        &eta,
+    );
+
+    client.supply_loan_tokens(&lender, &400000);
+    let loan_id = client.deposit_collateral(&borrower, &100);
+
+    assert_eq!(
+        client.try_queue_change(
+            &admin,
+            &ParamChange::LoanToken(new_loan_token.address.clone()),
+            &eta
+        ),
+        Err(Ok(Error::TokenInUse))
+    );
+
+    env.ledger().with_mut(|li| li.timestamp = eta);
+    oracle.set_price(&token_collateral.address, &(5000 * PRICE_SCALE));
+
+    // Checked again when the change lands.
+    assert_eq!(
+        client.try_execute_change(&loan_token_change),
+        Err(Ok(Error::TokenInUse))
+    );
+    assert_eq!(
+        client.try_execute_change(&collateral_token_change),
+        Err(Ok(Error::TokenInUse))
+    );
+
+    client.withdraw_loan_tokens(&lender, &400000);
+    client.remove_collateral(&loan_id, &100);
+
+    client.execute_change(&loan_token_change);
+    client.execute_change(&collateral_token_change);
+
+    assert_eq!(client.get_loan_token(), new_loan_token.address);
+    assert_eq!(client.get_collateral_token(), new_collateral_token.address);
+    assert_eq!(client.get_pending_changes().len(), 0);
+}
+
+#[test]
+fn bounded_parameter_setters() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let stranger = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+
+    assert_eq!(
+        client.try_set_collateral_rate(&stranger, &700000),
+        Err(Ok(Error::Unauthorized))
+    );
+    assert_eq!(
+        client.try_set_collateral_rate(&admin, &0),
+        Err(Ok(Error::InvalidCollateralRate))
+    );
+    assert_eq!(
+        client.try_set_collateral_rate(&admin, &850000),
+        Err(Ok(Error::InvalidCollateralRate))
+    );
+
+    // Raising what borrowers can take needs notice.
+    assert_eq!(
+        client.try_set_collateral_rate(&admin, &820000),
+        Err(Ok(Error::RequiresTimelock))
+    );
+
+    client.set_collateral_rate(&admin, &700000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("coll_rate")).into_val(&env),
+                700000_i128.into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_collateral_rate(), 700000);
+
+    // The threshold cannot drop to the collateral rate either.
+    assert_eq!(
+        client.try_set_liquidation_params(&admin, &700000, &50000),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    assert_eq!(
+        client.try_set_interest_rate(&admin, &-1),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+    assert_eq!(
+        client.try_set_interest_rate(&admin, &(MAX_INTEREST_RATE + 1)),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+
+    assert_eq!(
+        client.try_set_interest_rate(&admin, &MAX_INTEREST_RATE),
+        Err(Ok(Error::RequiresTimelock))
+    );
+
+    client.set_interest_rate(&admin, &10000);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("int_rate")).into_val(&env),
+                10000_i128.into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_rate_model(), InterestRateModel::flat(10000));
+
+    assert_eq!(
+        client.try_set_installments(&admin, &0),
+        Err(Ok(Error::InvalidInstallments))
+    );
+    assert_eq!(
+        client.try_set_installments(&admin, &(MAX_INSTALLMENTS + 1)),
+        Err(Ok(Error::InvalidInstallments))
+    );
+
+    client.set_installments(&admin, &12);
+
+    assert_eq!(
+        last_event(&env),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("admin"), symbol_short!("installs")).into_val(&env),
+                12_u32.into_val(&env),
+            )
+        ]
+    );
+
+    assert_eq!(client.get_installments(), 12);
+
+    // A risk manager raises rates through the timelock, tokens stay with the
+    // admin.
+    let risk_manager = Address::random(&env);
+
+    client.grant_role(&Role::RiskManager, &risk_manager);
+
+    let eta = env.ledger().timestamp() + TIMELOCK_DELAY;
+
+    let change = client.queue_change(
+        &risk_manager,
+        &ParamChange::InterestRate(MAX_INTEREST_RATE),
+        &eta,
+    );
+
+    assert_eq!(
+        client.try_queue_change(
+            &risk_manager,
+            &ParamChange::LoanToken(stranger.clone()),
+            &eta
+        ),
+        Err(Ok(Error::Unauthorized))
+    );
+    assert_eq!(
+        client.try_cancel_change(&stranger, &change),
+        Err(Ok(Error::Unauthorized))
+    );
+
+    env.ledger().with_mut(|li| li.timestamp = eta);
+
+    client.execute_change(&change);
+
+    assert_eq!(
+        client.get_rate_model(),
+        InterestRateModel::flat(MAX_INTEREST_RATE)
+    );
+
+    // The whole curve is capped, not just its base.
+    let kinked = InterestRateModel {
+        base_rate: 20000,
+        slope_1: 40000,
+        slope_2: MAX_INTEREST_RATE - 60000,
+        optimal_utilization: 800000,
+    };
+
+    assert_eq!(
+        client.try_set_rate_model(&risk_manager, &0, &0, &(MAX_INTEREST_RATE + 1), &800000),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+    assert_eq!(
+        client.try_set_rate_model(&risk_manager, &i128::MAX, &1, &0, &1000000),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+    assert_eq!(
+        client.try_queue_change(
+            &risk_manager,
+            &ParamChange::RateModel(InterestRateModel {
+                slope_2: MAX_INTEREST_RATE,
+                ..kinked.clone()
+            }),
+            &(eta + TIMELOCK_DELAY)
+        ),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+
+    // Moving the kink can raise some rates, so it always waits.
+    assert_eq!(
+        client.try_set_rate_model(&risk_manager, &0, &0, &0, &800000),
+        Err(Ok(Error::RequiresTimelock))
+    );
+
+    let change = client.queue_change(
+        &risk_manager,
+        &ParamChange::RateModel(kinked.clone()),
+        &(eta + TIMELOCK_DELAY),
+    );
+
+    env.ledger()
+        .with_mut(|li| li.timestamp = eta + TIMELOCK_DELAY);
+
+    client.execute_change(&change);
+
+    assert_eq!(client.get_rate_model(), kinked);
+
+    assert_eq!(
+        client.try_set_interest_rate(&risk_manager, &30000),
+        Err(Ok(Error::InvalidInterestRate))
+    );
+
+    client.set_rate_model(&risk_manager, &10000, &40000, &500000, &800000);
+
+    assert_eq!(client.get_rate_model().base_rate, 10000);
+}
+
+#[test]
+fn timelocked_borrower_terms() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let admin = Address::random(&env);
+    let stranger = Address::random(&env);
+
+    let (token_to_lend, _, token_collateral, _) = initialize_tokens(env.clone());
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    // Terms that were never set can be set without notice.
+    let oracle = setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_liquidation_params(&admin, &850000, &50000);
+    client.set_grace_period(&admin, &SECONDS_PER_DAY);
+    client.set_penalty_params(&admin, &1000, &500000);
+
+    let liquidation_params = || {
+        env.as_contract(&contract_id, || {
+            let storage = env.storage().instance();
+
+            (
+                storage
+                    .get::<_, i128>(&DataKey::LiquidationThreshold)
+                    .unwrap(),
+                storage.get::<_, i128>(&DataKey::LiquidationBonus).unwrap(),
+            )
+        })
+    };
+
+    // Changes in the borrowers' favour apply at once.
+    client.set_liquidation_params(&admin, &900000, &40000);
+    client.set_grace_period(&admin, &(2 * SECONDS_PER_DAY));
+    client.set_penalty_params(&admin, &500, &0);
+    client.set_oracle(&admin, &oracle.address, &600, &7);
+
+    assert_eq!(liquidation_params(), (900000, 40000));
+    assert_eq!(client.get_grace_period(), 2 * SECONDS_PER_DAY);
+    assert_eq!(client.get_penalty_params(), (500, 0));
+    assert_eq!(client.get_oracle().max_age, 600);
+
+    // Anything that could push a loan into liquidation, default or late fees
+    // needs notice.
+    assert_eq!(
+        client.try_set_liquidation_params(&admin, &850000, &40000),
+        Err(Ok(Error::RequiresTimelock))
+    );
+    assert_eq!(
+        client.try_set_liquidation_params(&admin, &900000, &50000),
+        Err(Ok(Error::RequiresTimelock))
+    );
+    assert_eq!(
+        client.try_set_grace_period(&admin, &SECONDS_PER_DAY),
+        Err(Ok(Error::RequiresTimelock))
+    );
+    assert_eq!(
+        client.try_set_penalty_params(&admin, &1000, &0),
+        Err(Ok(Error::RequiresTimelock))
+    );
+    assert_eq!(
+        client.try_set_oracle(&admin, &stranger, &600, &7),
+        Err(Ok(Error::RequiresTimelock))
+    );
+    assert_eq!(
+        client.try_set_oracle(&admin, &oracle.address, &600, &8),
+        Err(Ok(Error::RequiresTimelock))
+    );
+
+    let eta = env.ledger().timestamp() + TIMELOCK_DELAY;
+
+    assert_eq!(
+        client.try_queue_change(&admin, &ParamChange::LiquidationParams(800000, 50000), &eta),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_queue_change(
+            &admin,
+            &ParamChange::LiquidationParams(850000, MAX_LIQUIDATION_BONUS + 1),
+            &eta
+        ),
+        Err(Ok(Error::InvalidLiquidationBonus))
+    );
+    assert_eq!(
+        client.try_queue_change(
+            &admin,
+            &ParamChange::GracePeriod(MAX_GRACE_PERIOD + 1),
+            &eta
+        ),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_queue_change(&admin, &ParamChange::PenaltyParams(-1, 0), &eta),
+        Err(Ok(Error::InvalidParameter))
+    );
+
+    let new_oracle = OracleConfig {
+        address: stranger.clone(),
+        max_age: 600,
+        decimals: 8,
+    };
+
+    assert_eq!(
+        client.try_queue_change(
+            &admin,
+            &ParamChange::Oracle(OracleConfig {
+                max_age: 0,
+                ..new_oracle.clone()
+            }),
+            &eta
+        ),
+        Err(Ok(Error::InvalidParameter))
+    );
+    assert_eq!(
+        client.try_queue_change(&stranger, &ParamChange::GracePeriod(0), &eta),
+        Err(Ok(Error::Unauthorized))
+    );
+
+    let changes = [
+        client.queue_change(&admin, &ParamChange::LiquidationParams(850000, 50000), &eta),
+        client.queue_change(&admin, &ParamChange::GracePeriod(0), &eta),
+        client.queue_change(&admin, &ParamChange::PenaltyParams(1000, 500000), &eta),
+        client.queue_change(&admin, &ParamChange::Oracle(new_oracle.clone()), &eta),
+    ];
+
+    assert_eq!(
+        client.try_execute_change(&changes[0]),
+        Err(Ok(Error::ChangeNotReady))
+    );
+
+    env.ledger().with_mut(|li| li.timestamp = eta);
+
+    for change in changes {
+        client.execute_change(&change);
+    }
+
+    assert_eq!(liquidation_params(), (850000, 50000));
+    assert_eq!(client.get_grace_period(), 0);
+    assert_eq!(client.get_penalty_params(), (1000, 500000));
+    assert_eq!(client.get_oracle(), new_oracle);
+}
+
+#[test]
+fn reserves_above_cash_keep_the_pool_running() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &401000);
+    token_lend_admin.mint(&borrower, &10000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_reserve_factor(&admin, &100000);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    // Lends out the whole pool.
+    let loan_id = client.request_loan(&borrower, &100);
+
+    env.ledger().with_mut(|li| li.timestamp += SECONDS_PER_YEAR);
+
+    client.repay_loan(&loan_id, &1);
+
+    // Reserves now exceed the single unit of cash.
+    let state = client.get_pool_state();
+    assert!(state.total_reserves > state.total_cash);
+
+    env.ledger().with_mut(|li| li.timestamp += 100);
+
+    assert_eq!(client.get_current_rates(), (15000, 13500));
+
+    client.get_debt(&loan_id);
+    client.repay_loan(&loan_id, &1000);
+    client.supply_loan_tokens(&lender, &1000);
+}
+
+#[test]
+fn donated_tokens_do_not_block_the_pool() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let donor = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, _) = initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &400000);
+    token_lend_admin.mint(&donor, &1);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    token_to_lend.transfer(&donor, &contract_id, &1);
+
+    client.supply_loan_tokens(&lender, &400000);
+
+    assert_eq!(client.get_total_supply(), 400000);
+    assert_eq!(token_to_lend.balance(&contract_id), 400001);
+
+    client.withdraw_loan_tokens(&lender, &400000);
+
+    assert_eq!(token_to_lend.balance(&lender), 400000);
+}
+
+#[test]
+fn mark_default_writes_off_reserves_on_unpaid_interest() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, CollateralizedLoanContract);
+
+    let lender = Address::random(&env);
+    let borrower = Address::random(&env);
+    let treasury = Address::random(&env);
+
+    let (token_to_lend, token_lend_admin, token_collateral, token_collateral_admin) =
+        initialize_tokens(env.clone());
+
+    token_lend_admin.mint(&lender, &500000);
+    token_collateral_admin.mint(&borrower, &100);
+
+    let admin = Address::random(&env);
+
+    let client = CollateralizedLoanContractClient::new(&env, &contract_id);
+
+    client.initialize(
+        &admin,
+        &token_to_lend.address,
+        &token_collateral.address,
+        &800000,
+        &15000,
+        &3,
+    );
+
+    setup_oracle(&env, &client, &token_collateral.address);
+
+    client.set_reserve_factor(&admin, &100000);
+    client.set_treasury(&treasury);
+
+    client.supply_loan_tokens(&lender, &500000);
+
+    let loan_id = client.request_loan(&borrower, &100);
+
+    env.ledger()
+        .with_mut(|li| li.timestamp = 3 * INSTALLMENT_PERIOD + 1);
+
+    client.mark_default(&loan_id);
+
+    // Nothing was ever paid, so there is nothing to claim.
+    assert_eq!(client.get_reserves(), 0);
+    assert_eq!(
+        client.try_claim_reserves(&admin, &1),
+        Err(Ok(Error::InsufficientReserves))
+    );
+    assert_eq!(client.get_total_supply(), 100000);
+    assert_eq!(client.get_shares_value(&500000), 100000);
+}
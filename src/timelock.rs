@@ -0,0 +1,189 @@
+use soroban_sdk::{contracttype, token, Address, Env, Vec};
+
+use crate::{
+    oracle, params, DataKey, Error, InterestRateModel, OracleConfig, Role, SECONDS_PER_DAY,
+};
+
+/// Shortest notice a queued change gives before it can be executed.
+pub const TIMELOCK_DELAY: u64 = 2 * SECONDS_PER_DAY;
+
+/// A parameter change that has to wait out the timelock.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParamChange {
+    /// Share of the collateral value that can be borrowed, in millionths.
+    CollateralRate(i128),
+    /// Borrow rate at zero utilization, in millionths per year.
+    InterestRate(i128),
+    /// The whole borrow rate curve.
+    RateModel(InterestRateModel),
+    /// Liquidation threshold and bonus, in millionths.
+    LiquidationParams(i128, i128),
+    /// Seconds after the last due date before a loan can be defaulted.
+    GracePeriod(u64),
+    /// Daily penalty rate and lender share, in millionths.
+    PenaltyParams(i128, i128),
+    /// Price feed used to value collateral.
+    Oracle(OracleConfig),
+    /// Only while the pool holds no cash, borrows, shares or reserves.
+    LoanToken(Address),
+    /// Only while the contract holds no collateral.
+    CollateralToken(Address),
+}
+
+impl ParamChange {
+    /// Role that can queue and cancel the change.
+    pub fn role(&self) -> Role {
+        match self {
+            ParamChange::CollateralRate(_)
+            | ParamChange::InterestRate(_)
+            | ParamChange::RateModel(_)
+            | ParamChange::LiquidationParams(..)
+            | ParamChange::GracePeriod(_)
+            | ParamChange::PenaltyParams(..)
+            | ParamChange::Oracle(_) => Role::RiskManager,
+            ParamChange::LoanToken(_) | ParamChange::CollateralToken(_) => Role::Admin,
+        }
+    }
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PendingChange {
+    pub id: u64,
+    pub change: ParamChange,
+    /// Earliest time the change can be executed.
+    pub eta: u64,
+}
+
+pub fn read_pending(env: &Env) -> Vec<PendingChange> {
+    env.storage()
+        .instance()
+        .get(&DataKey::PendingChanges)
+        .unwrap_or(Vec::new(env))
+}
+
+pub fn queue(env: &Env, change: ParamChange, eta: u64) -> Result<PendingChange, Error> {
+    if eta < env.ledger().timestamp() + TIMELOCK_DELAY {
+        return Err(Error::DelayTooShort);
+    }
+
+    validate(env, &change)?;
+
+    let id: u64 = env
+        .storage()
+        .instance()
+        .get(&DataKey::NextChangeId)
+        .unwrap_or(0);
+
+    env.storage()
+        .instance()
+        .set(&DataKey::NextChangeId, &(id + 1));
+
+    let pending = PendingChange { id, change, eta };
+
+    let mut changes = read_pending(env);
+    changes.push_back(pending.clone());
+    write_pending(env, &changes);
+
+    Ok(pending)
+}
+
+/// Takes change `id` off the queue.
+pub fn take(env: &Env, id: u64) -> Result<PendingChange, Error> {
+    let mut changes = read_pending(env);
+
+    let index = changes
+        .iter()
+        .position(|pending| pending.id == id)
+        .ok_or(Error::ChangeNotFound)?;
+
+    let pending = changes.get_unchecked(index as u32);
+
+    changes.remove(index as u32);
+    write_pending(env, &changes);
+
+    Ok(pending)
+}
+
+/// Checks the change against the state it would land on.
+pub fn validate(env: &Env, change: &ParamChange) -> Result<(), Error> {
+    match change {
+        ParamChange::CollateralRate(rate) => params::check_collateral_rate(env, *rate)?,
+        ParamChange::InterestRate(rate) => params::check_interest_rate(env, *rate)?,
+        ParamChange::RateModel(model) => params::check_rate_model(model)?,
+        ParamChange::LiquidationParams(threshold, bonus) => {
+            params::check_liquidation_params(env, *threshold, *bonus)?
+        }
+        ParamChange::GracePeriod(grace_period) => params::check_grace_period(*grace_period)?,
+        ParamChange::PenaltyParams(rate, share) => params::check_penalty_params(*rate, *share)?,
+        ParamChange::Oracle(config) => oracle::check_config(config)?,
+        ParamChange::LoanToken(_) => {
+            let storage = env.storage().instance();
+
+            let in_use = [
+                DataKey::TotalCash,
+                DataKey::TotalBorrows,
+                DataKey::TotalShares,
+                DataKey::TotalReserves,
+            ]
+            .iter()
+            .any(|key| storage.get::<_, i128>(key).unwrap_or(0) != 0);
+
+            if in_use {
+                return Err(Error::TokenInUse);
+            }
+        }
+        ParamChange::CollateralToken(_) => {
+            let collateral_token: Address = env
+                .storage()
+                .instance()
+                .get(&DataKey::CollateralToken)
+                .ok_or(Error::NotInitialized)?;
+
+            let held =
+                token::Client::new(env, &collateral_token).balance(&env.current_contract_address());
+
+            if held != 0 {
+                return Err(Error::TokenInUse);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn apply(env: &Env, change: &ParamChange) -> Result<(), Error> {
+    validate(env, change)?;
+
+    match change {
+        ParamChange::CollateralRate(rate) => params::write_collateral_rate(env, *rate),
+        ParamChange::InterestRate(rate) => params::write_interest_rate(env, *rate)?,
+        ParamChange::RateModel(model) => params::write_rate_model(env, model)?,
+        ParamChange::LiquidationParams(threshold, bonus) => {
+            params::write_liquidation_params(env, *threshold, *bonus)
+        }
+        ParamChange::GracePeriod(grace_period) => params::write_grace_period(env, *grace_period),
+        ParamChange::PenaltyParams(rate, share) => params::write_penalty_params(env, *rate, *share),
+        ParamChange::Oracle(config) => oracle::write_config(env, config),
+        ParamChange::LoanToken(token) => write_token(env, DataKey::LoanToken, token),
+        ParamChange::CollateralToken(token) => write_token(env, DataKey::CollateralToken, token),
+    }
+
+    Ok(())
+}
+
+fn write_token(env: &Env, key: DataKey, token: &Address) {
+    env.storage().instance().set(&key, token);
+
+    env.storage().instance().set(
+        &DataKey::Decimals(token.clone()),
+        &token::Client::new(env, token).decimals(),
+    );
+}
+
+fn write_pending(env: &Env, changes: &Vec<PendingChange>) {
+    env.storage()
+        .instance()
+        .set(&DataKey::PendingChanges, changes);
+}